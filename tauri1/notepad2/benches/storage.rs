@@ -0,0 +1,159 @@
+//! Compares the rope behind `FileBuffer` with the plain `String` it replaced,
+//! and measures `FileBuffer` itself doing what the editor does on a keystroke.
+//!
+//! The `String` side mirrors the old implementation: character indices from the
+//! editor are turned into byte offsets by scanning from the start of the text.
+
+// The app's own modules, compiled in for `FileBuffer`; the bench uses only part of them.
+#![allow(dead_code, unused_imports)]
+
+#[path = "../src/atomic_write.rs"]
+mod atomic_write;
+#[path = "../src/encoding.rs"]
+mod encoding;
+#[path = "../src/error.rs"]
+mod error;
+#[path = "../src/file_buffer.rs"]
+mod file_buffer;
+#[path = "../src/highlight.rs"]
+mod highlight;
+#[path = "../src/history.rs"]
+mod history;
+#[path = "../src/language.rs"]
+mod language;
+#[path = "../src/line_ending.rs"]
+mod line_ending;
+#[path = "../src/line_rows.rs"]
+mod line_rows;
+#[path = "../src/search.rs"]
+mod search;
+
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use file_buffer::FileBuffer;
+use ropey::Rope;
+use search::{Matcher, SearchOptions};
+use std::hint::black_box;
+
+fn sample(lines: usize) -> String {
+    "2024-01-01 12:00:00 INFO request handled in 3ms — ok\n".repeat(lines)
+}
+
+fn byte_index(text: &str, char_index: usize) -> usize {
+    text.char_indices().nth(char_index).map_or(text.len(), |(i, _)| i)
+}
+
+fn string_line_start(text: &str, line: usize) -> usize {
+    if line == 0 {
+        return 0;
+    }
+    text.match_indices('\n').nth(line - 1).map_or(text.len(), |(i, _)| i + 1)
+}
+
+fn edits(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert_delete_middle");
+    for lines in [10_000, 100_000, 1_000_000] {
+        let text = sample(lines);
+        let middle = text.chars().count() / 2;
+
+        group.bench_with_input(BenchmarkId::new("string", lines), &text, |b, text| {
+            let mut s = text.clone();
+            b.iter(|| {
+                let at = byte_index(&s, middle);
+                s.insert_str(at, "typed");
+                let at = byte_index(&s, middle);
+                s.drain(at..at + 5);
+                black_box(s.len());
+            });
+        });
+
+        group.bench_with_input(BenchmarkId::new("rope", lines), &text, |b, text| {
+            let mut rope = Rope::from_str(text);
+            b.iter(|| {
+                rope.insert(middle, "typed");
+                rope.remove(middle..middle + 5);
+                black_box(rope.len_bytes());
+            });
+        });
+    }
+    group.finish();
+}
+
+fn line_lookup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("line_lookup");
+    for lines in [10_000, 100_000, 1_000_000] {
+        let text = sample(lines);
+        let target = lines * 3 / 4;
+
+        group.bench_with_input(BenchmarkId::new("string", lines), &text, |b, text| {
+            b.iter(|| black_box(string_line_start(text, black_box(target))));
+        });
+
+        let rope = Rope::from_str(&text);
+        group.bench_with_input(BenchmarkId::new("rope", lines), &rope, |b, rope| {
+            b.iter(|| black_box(rope.line_to_char(black_box(target))));
+        });
+    }
+    group.finish();
+}
+
+/// One keystroke through `FileBuffer`: the edit with its undo history, then
+/// reading back the lines the editor lays out around it.
+fn buffer_keystroke(c: &mut Criterion) {
+    let mut group = c.benchmark_group("file_buffer_keystroke");
+    for lines in [10_000, 100_000, 1_000_000] {
+        let mut buffer = FileBuffer::new("bench.log", sample(lines), None);
+        let middle = buffer.len_chars() / 2;
+        let line = buffer.char_to_line(middle);
+        group.bench_function(BenchmarkId::from_parameter(lines), |b| {
+            b.iter(|| {
+                buffer.insert_text("x", middle);
+                let window = buffer.line_to_char(line.saturating_sub(40))..buffer.line_to_char(line + 40);
+                black_box(buffer.text().slice(window).to_string());
+                buffer.delete_char_range(middle..middle + 1);
+            });
+        });
+    }
+    group.finish();
+}
+
+/// Searching the whole buffer, which walks the rope rather than a flat copy.
+fn buffer_search(c: &mut Criterion) {
+    let mut group = c.benchmark_group("file_buffer_find_all");
+    group.sample_size(10);
+    let matcher = Matcher::new("INFO", SearchOptions::default()).unwrap();
+    for lines in [10_000, 100_000, 1_000_000] {
+        let buffer = FileBuffer::new("bench.log", sample(lines), None);
+        group.bench_function(BenchmarkId::from_parameter(lines), |b| {
+            b.iter(|| black_box(buffer.find_all(&matcher).len()));
+        });
+    }
+    group.finish();
+}
+
+/// What the editor does with wrapped rows on a frame after pressing Enter:
+/// the edit splices a line into the row counts, then the view finds its top
+/// line, the rows above it and the height of the whole text.
+fn wrapped_rows(c: &mut Criterion) {
+    let mut group = c.benchmark_group("line_rows_wrapped");
+    for lines in [10_000, 100_000, 1_000_000] {
+        let mut buffer = FileBuffer::new("bench.log", sample(lines), None);
+        // About twenty characters to a row, so each sample line wraps onto three.
+        buffer.line_rows(200.0, 10.0);
+        let middle = buffer.len_chars() / 2;
+        let top_row = buffer.rows().rows_before(buffer.char_to_line(middle));
+        group.bench_function(BenchmarkId::from_parameter(lines), |b| {
+            b.iter(|| {
+                buffer.insert_text("\n", middle);
+                let rows = buffer.line_rows(200.0, 10.0);
+                let top = rows.line_at_row(black_box(top_row), lines + 2);
+                black_box((rows.rows_before(top), rows.rows_before(lines + 2)));
+                rows.measured(top, 2);
+                buffer.delete_char_range(middle..middle + 1);
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, edits, line_lookup, buffer_keystroke, buffer_search, wrapped_rows);
+criterion_main!(benches);
@@ -0,0 +1,213 @@
+//! Crash-safe file replacement.
+//!
+//! New content goes to a temporary file next to the target. It is synced to
+//! disk and then renamed over the target, so a crash or a full disk leaves
+//! either the old file or the new one, never a truncated mix.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Temporary names tried before giving up, when earlier ones are taken.
+const TEMP_ATTEMPTS: usize = 16;
+
+static NEXT_TEMP: AtomicU64 = AtomicU64::new(0);
+
+/// What to keep of the previous version when a file is overwritten.
+#[derive(Clone, Copy, PartialEq)]
+pub enum BackupMode {
+    None,
+    /// `notes.txt~`, replaced on every save.
+    Tilde,
+    /// `notes.txt.20240101-120000.bak`, one per save.
+    Timestamped,
+}
+
+/// Replaces `path` with whatever `write` produces, keeping the original's
+/// permissions and, if asked, a backup of it. On error the original is untouched
+/// and the message names the step that failed.
+pub fn write_file(
+    path: &Path,
+    backup: BackupMode,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+) -> io::Result<()> {
+    // Write through a symlink rather than replacing the link itself.
+    let target = match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_symlink() => fs::canonicalize(path)?,
+        _ => path.to_path_buf(),
+    };
+    let original = fs::metadata(&target).ok();
+    let dir = match target.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let (tmp, file) = create_temp(&target)?;
+
+    let result = (|| {
+        let mut writer = BufWriter::new(file);
+        write(&mut writer).map_err(|err| step("write", &tmp, err))?;
+        let file = writer.into_inner().map_err(|err| step("write", &tmp, err.into_error()))?;
+        file.sync_all().map_err(|err| step("flush to disk", &tmp, err))?;
+        drop(file);
+
+        if let Some(meta) = &original {
+            fs::set_permissions(&tmp, meta.permissions())
+                .map_err(|err| step("copy permissions to", &tmp, err))?;
+            if let Some(backup_path) = backup_path(&target, backup) {
+                fs::copy(&target, &backup_path).map_err(|err| step("back up to", &backup_path, err))?;
+            }
+        }
+
+        fs::rename(&tmp, &target).map_err(|err| step("replace", &target, err))
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+        return result;
+    }
+    // Make the rename itself durable. Not every platform can open a directory.
+    if let Ok(dir) = File::open(&dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+fn step(action: &str, path: &Path, err: io::Error) -> io::Error {
+    io::Error::new(err.kind(), format!("could not {} {}: {}", action, path.display(), err))
+}
+
+/// Creates a temporary file next to `target`. Names carry the pid and a
+/// per-save counter; one left behind by a crashed process, perhaps with the
+/// same pid, is skipped for the next name.
+fn create_temp(target: &Path) -> io::Result<(PathBuf, File)> {
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    let mut attempt = 0;
+    loop {
+        let n = NEXT_TEMP.fetch_add(1, Ordering::Relaxed);
+        let tmp = target.with_file_name(format!(".{}.{}.{}.tmp", name, std::process::id(), n));
+        match OpenOptions::new().write(true).create_new(true).open(&tmp) {
+            Ok(file) => return Ok((tmp, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists && attempt + 1 < TEMP_ATTEMPTS => attempt += 1,
+            Err(err) => return Err(step("create temporary file", &tmp, err)),
+        }
+    }
+}
+
+fn backup_path(target: &Path, mode: BackupMode) -> Option<PathBuf> {
+    let name = target.file_name()?.to_string_lossy();
+    match mode {
+        BackupMode::None => None,
+        BackupMode::Tilde => Some(target.with_file_name(format!("{}~", name))),
+        BackupMode::Timestamped => Some(target.with_file_name(format!("{}.{}.bak", name, timestamp(SystemTime::now())))),
+    }
+}
+
+/// `YYYYMMDD-HHMMSS` in UTC.
+fn timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("notepad2-write-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn replaces_content_and_keeps_a_tilde_backup() {
+        let dir = temp_dir("tilde");
+        let path = dir.join("notes.txt");
+        fs::write(&path, "old").unwrap();
+
+        write_file(&path, BackupMode::Tilde, |w| w.write_all(b"new")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(fs::read_to_string(dir.join("notes.txt~")).unwrap(), "old");
+        // Only the file and its backup; no temporary file left behind.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn failed_write_leaves_the_original_alone() {
+        let dir = temp_dir("fail");
+        let path = dir.join("notes.txt");
+        fs::write(&path, "precious").unwrap();
+
+        let err = write_file(&path, BackupMode::None, |w| {
+            w.write_all(b"half")?;
+            Err(io::Error::other("disk full"))
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains("could not write"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "precious");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn skips_temporary_files_left_by_a_crashed_save() {
+        let dir = temp_dir("stale");
+        let path = dir.join("notes.txt");
+        fs::write(&path, "old").unwrap();
+        // Whatever a crashed process with our pid left under the next names.
+        let next = NEXT_TEMP.load(Ordering::Relaxed);
+        for n in next..next + 3 {
+            fs::write(dir.join(format!(".notes.txt.{}.{}.tmp", std::process::id(), n)), "stale").unwrap();
+        }
+
+        write_file(&path, BackupMode::None, |w| w.write_all(b"new")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        // The stale files are someone else's to clean up.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 4);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = temp_dir("perms");
+        let path = dir.join("script.sh");
+        fs::write(&path, "echo hi").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o750)).unwrap();
+
+        write_file(&path, BackupMode::None, |w| w.write_all(b"echo bye")).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o750);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn timestamps_are_utc_calendar_dates() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_709_210_096); // 2024-02-29 12:34:56 UTC
+        assert_eq!(timestamp(time), "20240229-123456");
+        assert_eq!(timestamp(UNIX_EPOCH), "19700101-000000");
+    }
+}
@@ -0,0 +1,144 @@
+//! Periodic saving of modified tabs that already have a file on disk.
+
+use std::time::{Duration, Instant};
+
+use crate::atomic_write::BackupMode;
+use crate::file_buffer::FileBuffer;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum AutosaveMode {
+    Off,
+    /// Save a tab once it has gone `delay` without an edit.
+    AfterIdle,
+    /// Save every modified tab each `delay`, even while typing continues.
+    Interval,
+}
+
+pub struct Autosave {
+    pub mode: AutosaveMode,
+    pub delay: Duration,
+    last_run: Instant,
+}
+
+impl Default for Autosave {
+    fn default() -> Self {
+        Self {
+            // Saving behind the user's back is opt-in.
+            mode: AutosaveMode::Off,
+            delay: Duration::from_secs(10),
+            last_run: Instant::now(),
+        }
+    }
+}
+
+impl Autosave {
+    /// Saves whichever tabs are due and returns a status line when anything was attempted.
+    pub fn run(&mut self, buffers: &mut [FileBuffer]) -> Option<String> {
+        let due: Vec<usize> = match self.mode {
+            AutosaveMode::Off => return None,
+            AutosaveMode::AfterIdle => (0..buffers.len())
+                .filter(|&i| buffers[i].needs_autosave(self.delay))
+                .collect(),
+            AutosaveMode::Interval => {
+                if self.last_run.elapsed() < self.delay {
+                    return None;
+                }
+                self.last_run = Instant::now();
+                (0..buffers.len())
+                    .filter(|&i| buffers[i].is_dirty() && buffers[i].file_path.is_some())
+                    .collect()
+            }
+        };
+
+        let mut saved = Vec::new();
+        let mut failed = Vec::new();
+        for i in due {
+            let buffer = &mut buffers[i];
+            let note = buffer.line_endings.conversion_note();
+            // Backups come from deliberate saves only, not every few seconds.
+            match buffer.save(BackupMode::None) {
+                Ok(()) => saved.push(match note {
+                    Some(note) => format!("{} ({})", buffer.name, note),
+                    None => buffer.name.clone(),
+                }),
+                Err(err) => {
+                    // Wait a full delay before retrying instead of failing every frame.
+                    buffer.reset_edit_timer();
+                    failed.push(format!("{} ({})", buffer.name, err));
+                }
+            }
+        }
+
+        match (saved.is_empty(), failed.is_empty()) {
+            (true, true) => None,
+            (false, true) => Some(format!("Autosaved: {}", saved.join(", "))),
+            (true, false) => Some(format!("Autosave failed: {}", failed.join(", "))),
+            (false, false) => Some(format!(
+                "Autosaved: {}; failed: {}",
+                saved.join(", "),
+                failed.join(", ")
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("notepad2-autosave-{}-{}", std::process::id(), name));
+        path.display().to_string()
+    }
+
+    #[test]
+    fn idle_mode_saves_only_dirty_tabs_with_a_path() {
+        let path = temp_path("idle");
+        let mut buffers = vec![
+            FileBuffer::new("clean", String::new(), Some(temp_path("clean"))),
+            FileBuffer::new("dirty", String::new(), Some(path.clone())),
+            FileBuffer::new("Untitled", String::new(), None),
+        ];
+        buffers[1].insert_text("hello", 0);
+        buffers[2].insert_text("scratch", 0);
+        for buffer in &mut buffers {
+            buffer.last_edit_time = Instant::now() - Duration::from_secs(60);
+        }
+
+        let mut autosave = Autosave { mode: AutosaveMode::AfterIdle, ..Default::default() };
+        assert_eq!(autosave.run(&mut buffers).as_deref(), Some("Autosaved: dirty"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "hello");
+        assert!(!buffers[1].is_dirty());
+        assert!(buffers[2].is_dirty());
+        assert_eq!(autosave.run(&mut buffers), None);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn idle_mode_waits_for_the_delay() {
+        let mut buffers = vec![FileBuffer::new("dirty", String::new(), Some(temp_path("wait")))];
+        buffers[0].insert_text("x", 0);
+        let mut autosave = Autosave { mode: AutosaveMode::AfterIdle, ..Default::default() };
+        assert_eq!(autosave.run(&mut buffers), None);
+        assert!(buffers[0].is_dirty());
+    }
+
+    #[test]
+    fn off_by_default() {
+        let mut buffers = vec![FileBuffer::new("dirty", String::new(), Some(temp_path("off")))];
+        buffers[0].insert_text("x", 0);
+        buffers[0].last_edit_time = Instant::now() - Duration::from_secs(60);
+        assert_eq!(Autosave::default().run(&mut buffers), None);
+        assert!(buffers[0].is_dirty());
+    }
+
+    #[test]
+    fn failures_are_reported() {
+        let mut buffers = vec![FileBuffer::new("bad", String::new(), Some("/nonexistent/dir/file.txt".into()))];
+        buffers[0].insert_text("x", 0);
+        let mut autosave = Autosave { mode: AutosaveMode::Interval, delay: Duration::ZERO, ..Default::default() };
+        let report = autosave.run(&mut buffers).unwrap();
+        assert!(report.starts_with("Autosave failed: bad"));
+    }
+}
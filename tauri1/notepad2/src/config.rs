@@ -0,0 +1,606 @@
+//! Editor settings kept in `config.toml` under the user's config directory.
+//!
+//! The file is meant to be edited by hand as well as from the Settings menu.
+//! Writing it back only touches the settings that changed, so comments and
+//! layout survive, and edits made elsewhere are picked up while the app runs.
+//! Every key is optional; a bad value is reported and its default used.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use eframe::egui::{Context, FontFamily, KeyboardShortcut};
+use toml_edit::{Array, DocumentMut, Item, Table, Value, value};
+
+use crate::atomic_write::{self, BackupMode};
+use crate::autosave::AutosaveMode;
+use crate::fonts::{self, FontFiles};
+use crate::keymap::{self, Action, Keybindings};
+use crate::notepad_app::NotepadApp;
+use crate::theme::ThemePreference;
+
+/// How often the file is checked for changes made outside the app.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// `notepad2` under the user's config directory, home to the config file and themes.
+pub fn dir() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("notepad2"))
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Config {
+    /// Scale of the whole UI, text and widgets alike.
+    pub font_scale: f32,
+    /// Editor text size in points, independent of `font_scale`.
+    pub font_size: f32,
+    pub wrap_text: bool,
+    pub line_numbers: bool,
+    pub restore_tabs: bool,
+    pub theme: ThemePreference,
+    /// Spaces inserted for Tab when `soft_tabs` is on.
+    pub tab_width: usize,
+    pub soft_tabs: bool,
+    pub font_family: FontFamily,
+    /// A font file for the editor, overriding `font_family`, and fallbacks.
+    pub font_files: FontFiles,
+    pub autosave_mode: AutosaveMode,
+    pub autosave_secs: u64,
+    pub backup: BackupMode,
+    pub keybindings: Keybindings,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            font_scale: 1.0,
+            font_size: fonts::DEFAULT_FONT_SIZE,
+            wrap_text: true,
+            line_numbers: true,
+            restore_tabs: true,
+            theme: ThemePreference::System,
+            tab_width: 4,
+            soft_tabs: false,
+            font_family: FontFamily::Monospace,
+            font_files: FontFiles::default(),
+            autosave_mode: AutosaveMode::Off,
+            autosave_secs: 10,
+            backup: BackupMode::None,
+            keybindings: Keybindings::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn capture(app: &NotepadApp) -> Self {
+        Self {
+            font_scale: app.font_scale,
+            font_size: app.font_size,
+            wrap_text: app.wrap_text,
+            line_numbers: app.show_line_numbers,
+            restore_tabs: app.restore_tabs,
+            theme: app.theme_pref.clone(),
+            tab_width: app.tab_width,
+            soft_tabs: app.soft_tabs,
+            font_family: app.font_family.clone(),
+            font_files: app.font_files.clone(),
+            autosave_mode: app.autosave.mode,
+            autosave_secs: app.autosave.delay.as_secs(),
+            backup: app.backup,
+            keybindings: app.keybindings.clone(),
+        }
+    }
+
+    /// Reads the settings in `doc`, with a message for each key that is
+    /// unknown or holds a bad value.
+    pub fn from_document(doc: &DocumentMut) -> (Self, Vec<String>) {
+        let mut config = Self::default();
+        let mut errors = Vec::new();
+        for (key, item) in doc.iter() {
+            let result = match key {
+                "keybindings" => config.read_keybindings(item),
+                _ => config.read(key, item).map_err(|err| vec![err]),
+            };
+            if let Err(errs) = result {
+                errors.extend(errs.into_iter().map(|err| format!("{}: {}", key, err)));
+            }
+        }
+        (config, errors)
+    }
+
+    /// Applies each binding in the `[keybindings]` table on its own, so a bad
+    /// entry is reported without dropping the ones after it.
+    fn read_keybindings(&mut self, item: &Item) -> Result<(), Vec<String>> {
+        let table = item.as_table_like().ok_or_else(|| vec!["expected a table".to_string()])?;
+        let mut errors = Vec::new();
+        for (name, item) in table.iter() {
+            match binding(name, item) {
+                Ok((action, shortcuts)) => self.keybindings.set(action, shortcuts),
+                Err(err) => errors.push(err),
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn read(&mut self, key: &str, item: &Item) -> Result<(), String> {
+        match key {
+            "font_scale" => self.font_scale = number(item, 0.5, 2.5)? as f32,
+            "font_size" => {
+                let (min, max) = (fonts::MIN_FONT_SIZE as f64, fonts::MAX_FONT_SIZE as f64);
+                self.font_size = fonts::clamp_font_size(number(item, min, max)? as f32);
+            }
+            "font_file" => {
+                // An empty path means the built-in font, to make switching back easy.
+                let path = string(item)?;
+                self.font_files.editor = (!path.is_empty()).then(|| PathBuf::from(path));
+            }
+            "fallback_fonts" => {
+                let paths = item.as_array().ok_or("expected a list of font file paths")?;
+                self.font_files.fallbacks = paths
+                    .iter()
+                    .map(|path| path.as_str().map(PathBuf::from).ok_or("expected a font file path".to_string()))
+                    .collect::<Result<_, _>>()?;
+            }
+            "wrap_text" => self.wrap_text = boolean(item)?,
+            "line_numbers" => self.line_numbers = boolean(item)?,
+            "restore_tabs" => self.restore_tabs = boolean(item)?,
+            "theme" => self.theme = theme_from_name(string(item)?),
+            "tab_width" => self.tab_width = integer(item, 1, 16)? as usize,
+            "soft_tabs" => self.soft_tabs = boolean(item)?,
+            "font_family" => {
+                self.font_family = match string(item)? {
+                    "monospace" => FontFamily::Monospace,
+                    "proportional" => FontFamily::Proportional,
+                    _ => return Err("expected \"monospace\" or \"proportional\"".to_string()),
+                }
+            }
+            "backup" => {
+                self.backup = match string(item)? {
+                    "none" => BackupMode::None,
+                    "tilde" => BackupMode::Tilde,
+                    "timestamped" => BackupMode::Timestamped,
+                    _ => return Err("expected \"none\", \"tilde\" or \"timestamped\"".to_string()),
+                }
+            }
+            "autosave" => {
+                let table = item.as_table_like().ok_or("expected a table")?;
+                for (key, item) in table.iter() {
+                    match key {
+                        "mode" => {
+                            self.autosave_mode = match string(item)? {
+                                "off" => AutosaveMode::Off,
+                                "after-idle" => AutosaveMode::AfterIdle,
+                                "interval" => AutosaveMode::Interval,
+                                _ => return Err("mode: expected \"off\", \"after-idle\" or \"interval\"".to_string()),
+                            }
+                        }
+                        "seconds" => {
+                            self.autosave_secs = integer(item, 1, 300).map_err(|err| format!("seconds: {}", err))? as u64
+                        }
+                        _ => return Err(format!("unknown setting \"{}\"", key)),
+                    }
+                }
+            }
+            _ => return Err("unknown setting".to_string()),
+        }
+        Ok(())
+    }
+
+    /// Writes the settings that differ from `previous` into `doc`, or all of
+    /// them without a `previous`, leaving everything else as it was.
+    pub fn write_to(&self, previous: Option<&Config>, doc: &mut DocumentMut) {
+        macro_rules! set {
+            ($field:ident, $item:expr) => {
+                if previous.is_none_or(|previous| previous.$field != self.$field) {
+                    doc[stringify!($field)] = $item;
+                }
+            };
+        }
+        set!(font_scale, value((self.font_scale as f64 * 100.0).round() / 100.0));
+        set!(wrap_text, value(self.wrap_text));
+        set!(line_numbers, value(self.line_numbers));
+        set!(restore_tabs, value(self.restore_tabs));
+        set!(theme, value(theme_name(&self.theme)));
+        set!(tab_width, value(self.tab_width as i64));
+        set!(soft_tabs, value(self.soft_tabs));
+        let family = if self.font_family == FontFamily::Proportional { "proportional" } else { "monospace" };
+        set!(font_family, value(family));
+        set!(font_size, value(self.font_size as f64));
+        let files = &self.font_files;
+        if previous.is_none_or(|previous| previous.font_files.editor != files.editor) {
+            match &files.editor {
+                Some(path) => doc["font_file"] = value(path.display().to_string()),
+                None => {
+                    doc.remove("font_file");
+                }
+            }
+        }
+        if previous.is_none_or(|previous| previous.font_files.fallbacks != files.fallbacks) {
+            let paths: Array = files.fallbacks.iter().map(|path| path.display().to_string()).collect();
+            doc["fallback_fonts"] = value(paths);
+        }
+        let backup = match self.backup {
+            BackupMode::None => "none",
+            BackupMode::Tilde => "tilde",
+            BackupMode::Timestamped => "timestamped",
+        };
+        set!(backup, value(backup));
+
+        if previous.is_none_or(|previous| previous.autosave_mode != self.autosave_mode) {
+            let mode = match self.autosave_mode {
+                AutosaveMode::Off => "off",
+                AutosaveMode::AfterIdle => "after-idle",
+                AutosaveMode::Interval => "interval",
+            };
+            table(doc, "autosave")["mode"] = value(mode);
+        }
+        if previous.is_none_or(|previous| previous.autosave_secs != self.autosave_secs) {
+            table(doc, "autosave")["seconds"] = value(self.autosave_secs as i64);
+        }
+
+        for action in Action::ALL {
+            let shortcuts = self.keybindings.get(action);
+            if previous.is_some_and(|previous| previous.keybindings.get(action) == shortcuts) {
+                continue;
+            }
+            let mut texts = shortcuts.iter().map(keymap::format_shortcut);
+            table(doc, "keybindings")[action.name()] = match (texts.next(), shortcuts.len()) {
+                (Some(text), 1) => value(text),
+                (first, _) => value(first.into_iter().chain(texts).collect::<Array>()),
+            };
+        }
+    }
+}
+
+/// One `[keybindings]` entry: an action name and its shortcut or list of them.
+fn binding(name: &str, item: &Item) -> Result<(Action, Vec<KeyboardShortcut>), String> {
+    let action = Action::from_name(name).ok_or_else(|| format!("unknown action \"{}\"", name))?;
+    let shortcuts = match item.as_value() {
+        Some(Value::String(text)) => vec![keymap::parse_shortcut(text.value())],
+        Some(Value::Array(array)) => array
+            .iter()
+            .map(|value| value.as_str().ok_or("expected a shortcut string").map_err(String::from))
+            .map(|text| keymap::parse_shortcut(text?))
+            .collect(),
+        _ => return Err(format!("{}: expected a shortcut or a list of them", name)),
+    };
+    let shortcuts = shortcuts.into_iter().collect::<Result<_, _>>().map_err(|err| format!("{}: {}", name, err))?;
+    Ok((action, shortcuts))
+}
+
+/// The table at `key`, created if it is missing.
+fn table<'a>(doc: &'a mut DocumentMut, key: &str) -> &'a mut Table {
+    if !doc.get(key).is_some_and(Item::is_table) {
+        doc[key] = Item::Table(Table::new());
+    }
+    doc[key].as_table_mut().expect("just made a table")
+}
+
+fn boolean(item: &Item) -> Result<bool, String> {
+    item.as_bool().ok_or_else(|| "expected true or false".to_string())
+}
+
+fn string(item: &Item) -> Result<&str, String> {
+    item.as_str().ok_or_else(|| "expected a string".to_string())
+}
+
+fn integer(item: &Item, min: i64, max: i64) -> Result<i64, String> {
+    item.as_integer()
+        .filter(|n| (min..=max).contains(n))
+        .ok_or_else(|| format!("expected a whole number from {} to {}", min, max))
+}
+
+fn number(item: &Item, min: f64, max: f64) -> Result<f64, String> {
+    item.as_float()
+        .or_else(|| item.as_integer().map(|n| n as f64))
+        .filter(|n| (min..=max).contains(n))
+        .ok_or_else(|| format!("expected a number from {} to {}", min, max))
+}
+
+fn theme_from_name(name: &str) -> ThemePreference {
+    match name {
+        "system" => ThemePreference::System,
+        "light" => ThemePreference::Light,
+        "dark" => ThemePreference::Dark,
+        name => ThemePreference::Custom(name.to_string()),
+    }
+}
+
+fn theme_name(theme: &ThemePreference) -> &str {
+    match theme {
+        ThemePreference::System => "system",
+        ThemePreference::Light => "light",
+        ThemePreference::Dark => "dark",
+        ThemePreference::Custom(name) => name,
+    }
+}
+
+/// The config file on disk, with what was last read from or written to it.
+pub struct ConfigFile {
+    pub path: PathBuf,
+    /// The parsed file, kept so writing it back preserves comments. `None`
+    /// if the file is missing, or is not valid TOML and must not be overwritten.
+    document: Option<DocumentMut>,
+    /// Set when the file could not be parsed.
+    broken: bool,
+    /// The settings as of the last load or save, to tell when the user changes one.
+    pub saved: Config,
+    watcher: Option<Watcher>,
+    /// Modification time the file was left with by our own last write, which
+    /// the watcher will see but which is not an outside change.
+    written: Option<SystemTime>,
+}
+
+impl ConfigFile {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, document: None, broken: false, saved: Config::default(), watcher: None, written: None }
+    }
+
+    /// Reads the file, returning the settings and a message for each problem
+    /// in it. A missing file gives the defaults; unparsable TOML gives an error
+    /// and is left alone until fixed.
+    pub fn load(&mut self) -> Result<(Config, Vec<String>), String> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                self.document = None;
+                self.broken = false;
+                self.saved = Config::default();
+                return Ok((Config::default(), Vec::new()));
+            }
+            Err(err) => return Err(format!("{}: {}", self.path.display(), err)),
+        };
+        let doc = match text.parse::<DocumentMut>() {
+            Ok(doc) => doc,
+            Err(err) => {
+                self.broken = true;
+                return Err(format!("{}: {}", self.path.display(), err.to_string().trim_end()));
+            }
+        };
+        let (config, errors) = Config::from_document(&doc);
+        self.document = Some(doc);
+        self.broken = false;
+        self.saved = config.clone();
+        let errors = errors.into_iter().map(|err| format!("{}: {}", self.path.display(), err)).collect();
+        Ok((config, errors))
+    }
+
+    /// Writes `config` back, changing only the settings that differ from the
+    /// last load or save. A file that failed to parse is not touched.
+    pub fn save(&mut self, config: &Config) -> Result<(), String> {
+        let previous = std::mem::replace(&mut self.saved, config.clone());
+        if self.broken {
+            return Err(format!("{} has errors, so settings changes are not saved until it is fixed", self.path.display()));
+        }
+        let previous = self.document.is_some().then_some(&previous);
+        let doc = self.document.get_or_insert_with(DocumentMut::new);
+        config.write_to(previous, doc);
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(|err| format!("{}: {}", dir.display(), err))?;
+        }
+        let text = doc.to_string();
+        atomic_write::write_file(&self.path, BackupMode::None, |out| out.write_all(text.as_bytes()))
+            .map_err(|err| format!("Could not save settings: {}", err))?;
+        self.written = modified(&self.path);
+        Ok(())
+    }
+
+    /// Writes every setting, not only the changed ones, into the file.
+    pub fn save_all(&mut self, config: &Config) -> Result<(), String> {
+        if !self.broken {
+            self.document = None;
+        }
+        self.save(config)
+    }
+
+    /// Starts checking the file for outside changes; `ctx` is repainted when one is seen.
+    pub fn watch(&mut self, ctx: Context) {
+        self.watcher = Some(Watcher::start(self.path.clone(), ctx));
+    }
+
+    /// Whether the file has changed on disk since this was last asked, other
+    /// than by our own writes.
+    pub fn changed_on_disk(&self) -> bool {
+        self.watcher.as_ref().is_some_and(|watcher| watcher.changed.swap(false, Ordering::Relaxed))
+            && modified(&self.path) != self.written
+    }
+}
+
+/// Polls the file's modification time on a worker thread. Dropping it stops the worker.
+struct Watcher {
+    changed: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Watcher {
+    fn start(path: PathBuf, ctx: Context) -> Self {
+        let changed = Arc::new(AtomicBool::new(false));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (flag, stopped) = (changed.clone(), stop.clone());
+        thread::spawn(move || {
+            let mut last = modified(&path);
+            while !stopped.load(Ordering::Relaxed) {
+                thread::sleep(WATCH_INTERVAL);
+                let now = modified(&path);
+                if now != last {
+                    last = now;
+                    flag.store(true, Ordering::Relaxed);
+                    ctx.request_repaint();
+                }
+            }
+        });
+        Self { changed, stop }
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use eframe::egui::{Key, KeyboardShortcut, Modifiers};
+
+    fn parse(text: &str) -> (Config, Vec<String>) {
+        Config::from_document(&text.parse().unwrap())
+    }
+
+    #[test]
+    fn reads_settings_and_reports_bad_ones() {
+        let (config, errors) = parse(
+            r#"
+            font_scale = 1.5
+            wrap_text = false
+            theme = "Solarized"
+            tab_width = 40
+            soft_tabs = "yes"
+            colour = "red"
+
+            [autosave]
+            mode = "interval"
+            seconds = 30
+
+            [keybindings]
+            save = "Ctrl+Shift+S"
+            redo = ["Ctrl+Y"]
+            find = "Ctrl+Banana"
+            "#,
+        );
+        assert_eq!(config.font_scale, 1.5);
+        assert!(!config.wrap_text);
+        assert!(config.theme == ThemePreference::Custom("Solarized".to_string()));
+        assert!(config.autosave_mode == AutosaveMode::Interval);
+        assert_eq!(config.autosave_secs, 30);
+        let save = KeyboardShortcut::new(Modifiers::COMMAND | Modifiers::SHIFT, Key::S);
+        assert_eq!(config.keybindings.get(Action::Save), &[save]);
+        assert_eq!(config.keybindings.get(Action::Redo), &[KeyboardShortcut::new(Modifiers::COMMAND, Key::Y)]);
+
+        // Bad values fall back to the defaults.
+        assert_eq!(config.tab_width, 4);
+        assert!(!config.soft_tabs);
+        assert_eq!(errors.len(), 4, "{:?}", errors);
+        assert!(errors[0].starts_with("tab_width: expected a whole number from 1 to 16"));
+        assert!(errors[1].starts_with("soft_tabs:"));
+        assert!(errors[2].starts_with("colour: unknown setting"));
+        assert!(errors[3].contains("\"Banana\" is not a key"));
+    }
+
+    #[test]
+    fn a_bad_keybinding_does_not_drop_the_others() {
+        let (config, errors) = parse(
+            r#"
+            [keybindings]
+            frobnicate = "Ctrl+F"
+            find = "Ctrl+Banana"
+            save = "Ctrl+Shift+S"
+            "#,
+        );
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert!(errors[0].starts_with("keybindings: unknown action \"frobnicate\""));
+        assert!(errors[1].starts_with("keybindings: find:"));
+        assert_eq!(config.keybindings.get(Action::Find), Keybindings::default().get(Action::Find));
+        let save = KeyboardShortcut::new(Modifiers::COMMAND | Modifiers::SHIFT, Key::S);
+        assert_eq!(config.keybindings.get(Action::Save), &[save]);
+    }
+
+    #[test]
+    fn writing_changes_keeps_comments_and_untouched_values() {
+        let text = "# My settings\nwrap_text = true # always\ntab_width = 8\n";
+        let mut doc: DocumentMut = text.parse().unwrap();
+        let (before, _) = Config::from_document(&doc);
+        let mut after = before.clone();
+        after.tab_width = 2;
+        after.soft_tabs = true;
+        after.write_to(Some(&before), &mut doc);
+        let written = doc.to_string();
+        assert!(written.starts_with("# My settings\nwrap_text = true # always\ntab_width = 2\n"), "{}", written);
+        assert!(written.contains("soft_tabs = true"));
+        assert!(!written.contains("font_scale"));
+
+        let (reread, errors) = Config::from_document(&written.parse().unwrap());
+        assert!(errors.is_empty());
+        assert!(reread == after);
+    }
+
+    #[test]
+    fn a_full_write_reads_back_the_same() {
+        let config = Config {
+            theme: ThemePreference::Dark,
+            backup: BackupMode::Timestamped,
+            font_family: FontFamily::Proportional,
+            font_size: 17.5,
+            font_files: FontFiles {
+                editor: Some(PathBuf::from("/fonts/Iosevka.ttf")),
+                fallbacks: vec![PathBuf::from("/fonts/NotoSansCJK.otf")],
+            },
+            ..Default::default()
+        };
+        let mut doc = DocumentMut::new();
+        config.write_to(None, &mut doc);
+        let (reread, errors) = Config::from_document(&doc.to_string().parse().unwrap());
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert!(reread == config);
+    }
+
+    #[test]
+    fn an_unparsable_file_is_not_overwritten() {
+        let dir = std::env::temp_dir().join(format!("notepad2-config-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        fs::write(&path, "wrap_text = \n").unwrap();
+
+        let mut file = ConfigFile::new(path.clone());
+        assert!(file.load().is_err());
+        assert!(file.save(&Config::default()).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "wrap_text = \n");
+
+        fs::write(&path, "wrap_text = false\n").unwrap();
+        let (config, errors) = file.load().unwrap();
+        assert!(!config.wrap_text && errors.is_empty());
+        let mut changed = config.clone();
+        changed.line_numbers = false;
+        file.save(&changed).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "wrap_text = false\nline_numbers = false\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn our_own_writes_are_not_outside_changes() {
+        let dir = std::env::temp_dir().join(format!("notepad2-config-watch-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("config.toml");
+        let mut file = ConfigFile::new(path.clone());
+        // Stands in for the polling thread noticing a new modification time.
+        let seen = |file: &mut ConfigFile| {
+            file.watcher = Some(Watcher { changed: Arc::new(AtomicBool::new(true)), stop: Arc::new(AtomicBool::new(false)) });
+        };
+
+        file.save(&Config::default()).unwrap();
+        seen(&mut file);
+        assert!(!file.changed_on_disk());
+
+        fs::write(&path, "wrap_text = false\n").unwrap();
+        let later = file.written.unwrap() + Duration::from_secs(5);
+        fs::File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+        seen(&mut file);
+        assert!(file.changed_on_disk());
+        // Only once per change.
+        assert!(!file.changed_on_disk());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
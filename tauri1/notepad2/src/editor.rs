@@ -1,8 +1,19 @@
-use eframe::egui::{self, TextEdit, Ui};
-use std::fs;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use eframe::egui::{self, Align2, Color32, FontFamily, FontId, Galley, Pos2, Rect, TextBuffer, TextEdit, TextFormat, Ui};
+use egui::text::{CCursor, CCursorRange, LayoutJob};
+
+use crate::encoding::TextEncoding;
 use crate::file_buffer::FileBuffer;
-use crate::notepad_app::NotepadApp;
+use crate::fonts;
+use crate::goto;
+use crate::highlight::TokenKind;
+use crate::language::Language;
+use crate::line_rows::LineRows;
+use crate::notepad_app::{CloseRequest, NotepadApp};
+use crate::theme::SyntaxColors;
 
 impl NotepadApp {
     pub fn show_tabs(&mut self, ui: &mut Ui) {
@@ -20,19 +31,27 @@ impl NotepadApp {
                             if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
                                 if let Some(tab) = self.buffers.get_mut(i) {
                                     tab.name = self.rename_buffer.clone();
-                                    if let Some(path) = &tab.file_path {
-                                        let _ = fs::write(path, &tab.content);
-                                        self.status = format!("Renamed and saved: {}", path);
+                                    if let Some(path) = tab.file_path.clone() {
+                                        let note = tab.line_endings.conversion_note().map(|note| format!("; {}", note));
+                                        match tab.save(self.backup) {
+                                            Ok(()) => self.status.set(format!(
+                                                "Renamed and saved: {}{}",
+                                                path,
+                                                note.unwrap_or_default()
+                                            )),
+                                            Err(err) => self.report_error(format!("Renamed, but failed to save: {}", err)),
+                                        }
                                     }
                                 }
                                 self.editing_tab_index = None;
                             }
                         } else {
                             let tab_name = &self.buffers[i].name;
+                            let marker = if self.buffers[i].is_dirty() { " \u{25CF}" } else { "" };
                             let label = if is_selected {
-                                format!("[{}]", tab_name)
+                                format!("[{}{}]", tab_name, marker)
                             } else {
-                                tab_name.clone()
+                                format!("{}{}", tab_name, marker)
                             };
 
                             let response = ui.selectable_label(is_selected, label);
@@ -54,29 +73,751 @@ impl NotepadApp {
         });
 
         if let Some(i) = close_tab {
-            self.buffers.remove(i);
-            if self.current_tab >= self.buffers.len() {
-                self.current_tab = self.buffers.len().saturating_sub(1);
+            if self.buffers[i].is_dirty() {
+                self.pending_close = Some(CloseRequest::Tab(i));
+            } else {
+                self.close_tab(i);
+            }
+        }
+    }
+
+    pub fn close_tab(&mut self, i: usize) {
+        self.buffers.remove(i);
+        // Tabs after the closed one shift down, so keep pointing at the same tab.
+        if self.current_tab > i {
+            self.current_tab -= 1;
+        }
+        if self.current_tab >= self.buffers.len() {
+            self.current_tab = self.buffers.len().saturating_sub(1);
+        }
+        self.editing_tab_index = match self.editing_tab_index {
+            Some(editing) if editing == i => None,
+            Some(editing) if editing > i => Some(editing - 1),
+            editing => editing,
+        };
+    }
+
+    /// Saves a tab, asking for a path first if it has none. Returns whether it was saved.
+    pub fn save_tab(&mut self, i: usize) -> bool {
+        let Some(buffer) = self.buffers.get_mut(i) else {
+            return false;
+        };
+        let save_path = buffer.file_path.clone().or_else(|| {
+            rfd::FileDialog::new()
+                .set_file_name(&buffer.name)
+                .save_file()
+                .map(|p| p.display().to_string())
+        });
+        let Some(path) = save_path else {
+            return false;
+        };
+        let previous_path = buffer.file_path.replace(path.clone());
+        let note = buffer.line_endings.conversion_note().map(|note| format!("; {}", note));
+        match buffer.save(self.backup) {
+            Ok(()) => {
+                self.status.set(format!("Saved: {}{}", path, note.unwrap_or_default()));
+                true
+            }
+            Err(err) => {
+                // A failed Save As leaves the tab pointing where it did before.
+                buffer.file_path = previous_path;
+                self.report_error(format!("Failed to save: {}", err));
+                false
+            }
+        }
+    }
+
+    /// Saves a tab in a different encoding. The old encoding stays if the save fails.
+    pub fn save_tab_with_encoding(&mut self, i: usize, encoding: TextEncoding) -> bool {
+        let Some(buffer) = self.buffers.get_mut(i) else {
+            return false;
+        };
+        let previous = std::mem::replace(&mut buffer.encoding, encoding);
+        let saved = self.save_tab(i);
+        if !saved {
+            self.buffers[i].encoding = previous;
+        }
+        saved
+    }
+
+    /// Moves the cursor to what the Go to Line input names and scrolls it into
+    /// view. Leaves the dialog open with the reason if the input is invalid.
+    pub fn goto(&mut self) {
+        let (Some(dialog), Some(buffer)) = (&mut self.goto_dialog, self.buffers.get_mut(self.current_tab)) else {
+            return;
+        };
+        match goto::resolve(&dialog.input, buffer) {
+            Ok(index) => {
+                buffer.cursor = index;
+                buffer.pending_selection = Some(index..index);
+                self.goto_dialog = None;
+                self.focus_editor = true;
             }
-            if self.editing_tab_index == Some(i) {
-                self.editing_tab_index = None;
+            Err(err) => dialog.error = Some(err),
+        }
+    }
+
+    /// Decodes an unmodified tab's file again as `encoding`.
+    pub fn reopen_tab_with_encoding(&mut self, i: usize, encoding: TextEncoding) {
+        let Some(buffer) = self.buffers.get_mut(i) else {
+            return;
+        };
+        match buffer.reopen_with(encoding) {
+            Ok(()) => {
+                let message = format!("Reopened {} as {}", buffer.name, encoding);
+                self.status.set(message);
             }
+            Err(err) => self.report_error(format!("Could not reopen {}", err)),
+        }
+    }
+
+    /// Sets the editor text size, kept within the supported range.
+    pub fn set_font_size(&mut self, size: f32) {
+        let size = fonts::clamp_font_size(size);
+        if size != self.font_size {
+            self.font_size = size;
+            self.settings_changed = true;
+            self.status.set(format!("Editor font size: {} pt", size));
         }
     }
 
     pub fn show_editor(&mut self, ui: &mut Ui) {
+        // Ctrl+scroll and pinch over the editor size its text, not the whole UI.
+        if ui.rect_contains_pointer(ui.available_rect_before_wrap()) {
+            let zoom = ui.input(|input| input.zoom_delta());
+            if zoom != 1.0 {
+                self.set_font_size(self.font_size * zoom);
+            }
+        }
+
+        let wrap_text = self.wrap_text;
+        let show_line_numbers = self.show_line_numbers;
+        let syntax_colors = self.syntax_colors;
+        // A font file only becomes usable once egui has loaded it, the frame after it is set.
+        let editor_family = FontFamily::Name(fonts::EDITOR_FAMILY.into());
+        let font_family = if self.font_files.editor.is_some() && ui.fonts(|f| f.families().contains(&editor_family)) {
+            editor_family
+        } else {
+            self.font_family.clone()
+        };
+        let font_size = self.font_size;
+        let soft_tabs = self.soft_tabs.then_some(self.tab_width);
+        // Highlight matches only while the find bar is open and they are up to date.
+        let highlights = match self.buffers.get(self.current_tab) {
+            Some(buffer) if self.find_bar.is_open() && self.find_searched == Some((buffer.id, buffer.version())) => {
+                self.find_matches.as_slice()
+            }
+            _ => &[],
+        };
+        let current_match = self.find_current;
+        let focus_editor = self.find_bar.take_editor_focus() | std::mem::take(&mut self.focus_editor);
         if let Some(buffer) = self.buffers.get_mut(self.current_tab) {
-            let mut edit = TextEdit::multiline(&mut buffer.content)
-                .font(egui::TextStyle::Monospace)
-                .frame(true)
-                .lock_focus(true);
+            let id = buffer.id;
+            let language = buffer.language();
+            let pending_cursor = buffer.pending_cursor.take();
+            let pending_selection = buffer.pending_selection.take();
+            let mut scroll_area = egui::ScrollArea::both().id_salt(("editor_scroll", id));
+            let mut scroll_y = buffer.scroll.y;
+            if let Some(offset) = buffer.pending_scroll.take() {
+                scroll_area = scroll_area.scroll_offset(offset);
+                scroll_y = offset.y;
+            }
+
+            // The gutter sits outside the scroll area so it stays put when the
+            // text scrolls sideways; its numbers follow the text's rows.
+            let font_id = FontId::new(font_size, font_family);
+            let gutter = show_line_numbers.then(|| {
+                let digits = buffer.len_lines().to_string().len().max(2);
+                let digit_width = ui.fonts(|fonts| fonts.glyph_width(&font_id, '0'));
+                let width = digits as f32 * digit_width + GUTTER_PADDING * 2.0;
+                let rect = ui.available_rect_before_wrap();
+                egui::Rect::from_min_size(rect.min, egui::vec2(width, rect.height()))
+            });
+            let mut editor_rect = ui.available_rect_before_wrap();
+            if let Some(gutter) = gutter {
+                editor_rect.min.x = gutter.max.x;
+            }
+            let mut editor_ui = ui.new_child(egui::UiBuilder::new().max_rect(editor_rect));
+            let ui = &mut editor_ui;
+            // The layouter picks the editor font up from here too.
+            ui.style_mut().override_font_id = Some(font_id.clone());
+
+            // A separate widget id per tab keeps each tab's cursor and selection apart.
+            let editor_id = egui::Id::new(("editor", id));
+            let focused = ui.memory(|memory| memory.has_focus(editor_id));
+            if let Some(tab_width) = soft_tabs
+                && focused
+            {
+                // Typed text replaces the selection just as the tab character would.
+                let start = buffer.selection.start.min(buffer.cursor);
+                let column = start - buffer.line_to_char(buffer.char_to_line(start));
+                let spaces = " ".repeat(tab_width - column % tab_width);
+                ui.input_mut(|input| {
+                    for event in &mut input.events {
+                        if let egui::Event::Key { key: egui::Key::Tab, pressed: true, modifiers, .. } = event
+                            && modifiers.is_none()
+                        {
+                            *event = egui::Event::Text(spaces.clone());
+                        }
+                    }
+                });
+            }
+
+            // Where the cursor and selection are to be this frame, in the whole buffer.
+            let (mut cursor, mut selection) = match (&pending_selection, pending_cursor) {
+                (Some(selection), _) => (selection.end, selection.clone()),
+                (None, Some(index)) => (index, index..index),
+                (None, None) => (buffer.cursor, buffer.selection.clone()),
+            };
+            let mut target = pending_selection.as_ref().map(|selection| selection.start).or(pending_cursor);
+            if focused && let Some(jump) = whole_buffer_keys(ui, buffer.len_chars(), cursor, &selection) {
+                (cursor, selection, target) = jump;
+            }
+
+            // Only the lines in view, and the selection when it is small enough,
+            // are copied out and laid out; the space above and below stands in
+            // for the rest, sized by the rows each line wraps onto.
+            let row_height = ui.fonts(|fonts| fonts.row_height(&font_id));
+            let char_width = ui.fonts(|fonts| fonts.glyph_width(&font_id, '0'));
+            let wrap_width = if wrap_text { editor_rect.width() } else { f32::INFINITY };
+            buffer.line_rows(wrap_width, char_width);
+            let view_rows = (editor_rect.height() / row_height).ceil() as usize + 1;
+            let acts = focused && ui.input(|input| input.events.iter().any(acts_on_cursor));
+            let (lines, window) = loop {
+                let top_row = (scroll_y.max(0.0) / row_height) as usize;
+                let in_view = |index: usize| {
+                    let rows = line_span(buffer, buffer.char_to_line(index));
+                    rows.start < top_row + view_rows && top_row < rows.end
+                };
+                // Typing or a jump somewhere off screen scrolls there first.
+                let bring = target.or((acts && !in_view(cursor)).then_some(cursor));
+                if let Some(index) = bring
+                    && !in_view(index)
+                {
+                    let row = buffer.rows().rows_before(buffer.char_to_line(index)) as f32;
+                    scroll_y = (row * row_height - editor_rect.height() / 2.0).max(0.0);
+                    scroll_area = scroll_area.vertical_scroll_offset(scroll_y);
+                    continue;
+                }
+                let top = buffer.rows().line_at_row(top_row, buffer.len_lines());
+                let selected = buffer.char_to_line(selection.start)..buffer.char_to_line(selection.end) + 1;
+                let lines = window_lines(buffer.len_lines(), top, view_rows, selected);
+                let window = buffer.line_to_char(lines.start)..match lines.end < buffer.len_lines() {
+                    // Up to, not including, the line break after the last line.
+                    true => buffer.line_to_char(lines.end) - 1,
+                    false => buffer.len_chars(),
+                };
+                let shown = window.start <= selection.start && selection.end <= window.end;
+                if focused
+                    && !shown
+                    && !selection.is_empty()
+                    && let Some(index) = edit_large_selection(ui, buffer, selection.clone())
+                {
+                    (cursor, selection, target) = (index, index..index, Some(index));
+                    continue;
+                }
+                break (lines, window);
+            };
+            let rows_above = buffer.rows().rows_before(lines.start);
+            let rows_below = buffer.rows().rows_before(buffer.len_lines()) - buffer.rows().rows_before(lines.end);
+
+            // The window only holds part of a large selection; TextEdit shows that
+            // part, and the buffer keeps the whole of it until the user moves on.
+            let clamp = |index: usize| CCursor::new(index.clamp(window.start, window.end) - window.start);
+            let anchor = if cursor == selection.start { selection.end } else { selection.start };
+            let shown = CCursorRange::two(clamp(anchor), clamp(cursor));
+            if let Some(mut state) = TextEdit::load_state(ui.ctx(), editor_id)
+                && (state.cursor.char_range().is_some() || target.is_some())
+            {
+                state.cursor.set_char_range(Some(shown));
+                state.store(ui.ctx(), editor_id);
+            } else if target.is_some() {
+                let mut state = egui::text_edit::TextEditState::default();
+                state.cursor.set_char_range(Some(shown));
+                state.store(ui.ctx(), editor_id);
+            }
+
+            // Find matches are byte ranges in the whole buffer.
+            let window_bytes = buffer.char_to_byte(window.start)..buffer.char_to_byte(window.end);
+            let first_match = highlights.partition_point(|m| m.start < window_bytes.start);
+            let window_matches: Vec<Range<usize>> = highlights[first_match..]
+                .iter()
+                .take_while(|m| m.start < window_bytes.end)
+                .map(|m| m.start - window_bytes.start..m.end.min(window_bytes.end) - window_bytes.start)
+                .collect();
+            let current_match = current_match
+                .and_then(|i| i.checked_sub(first_match))
+                .filter(|i| *i < window_matches.len());
+
+            let line_state = buffer.highlight_state(lines.start, Instant::now() + HIGHLIGHT_BUDGET);
+            if line_state.is_none() && language != Language::PlainText {
+                // Still parsing up to the window; it is shown uncolored until then.
+                ui.ctx().request_repaint();
+            }
+            let span_cache = &mut self.span_cache;
+            let buffer_lines = buffer.len_lines();
+            let scrolled = scroll_area.show(ui, |ui| {
+                ui.add_space(rows_above as f32 * row_height);
+                let mut text = Window::new(buffer, window.clone());
+                // Clicks below the last line still land in the editor.
+                let min_height = if lines.end == buffer_lines { ui.available_height() } else { 0.0 };
+                let mut edit = TextEdit::multiline(&mut text)
+                    .id(editor_id)
+                    .frame(true)
+                    .lock_focus(true)
+                    .min_size(egui::vec2(ui.available_width(), min_height));
+
+                if !wrap_text {
+                    edit = edit.desired_width(f32::INFINITY);
+                } else {
+                    edit = edit.desired_width(ui.available_width());
+                }
+
+                let mut layouter = |ui: &Ui, text: &str, wrap_width: f32| {
+                    let spans = line_state.as_ref().map(|state| span_cache.spans(text, state)).unwrap_or_default();
+                    layout_text(ui, text, wrap_width, spans, &syntax_colors, &window_matches, current_match)
+                };
+                if !window_matches.is_empty() || language != Language::PlainText {
+                    edit = edit.layouter(&mut layouter);
+                }
 
-            if !self.wrap_text {
-                edit = edit.desired_width(f32::INFINITY);
+                let output = edit.show(ui);
+                ui.add_space(rows_below as f32 * row_height);
+                if focus_editor {
+                    output.response.request_focus();
+                }
+                if let Some(selection) = &pending_selection
+                    && window.contains(&selection.start)
+                {
+                    let rect = output.galley.pos_from_ccursor(CCursor::new(selection.start - window.start));
+                    ui.scroll_to_rect(rect.translate(output.galley_pos.to_vec2()), Some(egui::Align::Center));
+                }
+                output
+            });
+            let mut output = scrolled.inner;
+
+            // TextEdit keeps its own whole-text snapshots for undo; FileBuffer's
+            // history replaces them, so drop them instead of letting them pile up.
+            output.state.clear_undoer();
+            match output.cursor_range {
+                // Anything TextEdit did to the cursor, from a click to typing, wins.
+                Some(range) if [range.primary.ccursor, range.secondary.ccursor] != [shown.primary, shown.secondary] => {
+                    let [primary, secondary] = [range.primary.ccursor.index, range.secondary.ccursor.index].map(|i| i + window.start);
+                    buffer.cursor = primary;
+                    buffer.selection = primary.min(secondary)..primary.max(secondary);
+                }
+                _ => {
+                    buffer.cursor = cursor;
+                    buffer.selection = selection;
+                }
+            }
+            buffer.scroll = scrolled.state.offset;
+            output.state.store(ui.ctx(), output.response.id);
+
+            // Lines laid out on more or fewer rows than assumed move everything
+            // below them; scroll by as much as moved above the view to keep it still.
+            let top = buffer.rows().line_at_row((buffer.scroll.y.max(0.0) / row_height) as usize, buffer.len_lines());
+            let moved = measure_rows(buffer.line_rows(wrap_width, char_width), &output.galley, lines.start, top);
+            if moved != 0 {
+                buffer.pending_scroll = Some(buffer.scroll + egui::vec2(0.0, moved as f32 * row_height));
+                ui.ctx().request_repaint();
             }
 
-            ui.add_sized(ui.available_size(), edit);
-            buffer.push_undo();
+            let cursor_line = buffer.char_to_line(buffer.cursor);
+            let painter = ui.painter_at(scrolled.inner_rect);
+            paint_current_line(&painter, &output.galley, output.galley_pos, output.text_clip_rect, lines.start, cursor_line);
+            if let Some(gutter) = gutter {
+                let painter = ui.painter_at(gutter);
+                paint_gutter(&painter, &output.galley, output.galley_pos, font_id, lines.start, cursor_line);
+            }
         }
     }
-}
\ No newline at end of file
+}
+
+/// Screen rows, counted from the top of the text, that `line` is shown on.
+fn line_span(buffer: &FileBuffer, line: usize) -> Range<usize> {
+    let start = buffer.rows().rows_before(line);
+    start..start + buffer.rows().rows(line)
+}
+
+/// Records the rows each line of `galley`, starting at `first_line`, was laid
+/// out on. Returns the change in rows taken by the lines before `top`.
+fn measure_rows(rows: &mut LineRows, galley: &Galley, first_line: usize, top: usize) -> i64 {
+    let mut moved = 0;
+    let mut count = 0;
+    let mut lines = row_lines(galley, first_line).peekable();
+    while let Some((line, _, _)) = lines.next() {
+        count += 1;
+        if lines.peek().is_none_or(|(next, _, _)| *next != line) {
+            let change = rows.measured(line, count);
+            if line < top {
+                moved += change;
+            }
+            count = 0;
+        }
+    }
+    moved
+}
+
+/// Lines laid out beyond those on screen, above and below.
+const WINDOW_MARGIN: usize = 20;
+
+/// A selection spanning more lines than this is only laid out where it is on screen.
+const MAX_WINDOW_LINES: usize = 2_000;
+
+/// How long a frame spends parsing for syntax highlighting up to the lines on
+/// screen, so opening or jumping through a large file stays responsive.
+const HIGHLIGHT_BUDGET: Duration = Duration::from_millis(20);
+
+/// The lines the editor lays out: those on screen, from `top`, plus a margin,
+/// widened to take in the `selected` lines and one line either side of them
+/// when that stays within [`MAX_WINDOW_LINES`].
+fn window_lines(len_lines: usize, top: usize, rows: usize, selected: Range<usize>) -> Range<usize> {
+    let view = top.saturating_sub(WINDOW_MARGIN)..top + rows + WINDOW_MARGIN;
+    let around = selected.start.saturating_sub(1)..selected.end + 1;
+    let union = view.start.min(around.start)..view.end.max(around.end);
+    let mut lines = if union.len() <= MAX_WINDOW_LINES { union } else { view };
+    lines.end = lines.end.min(len_lines);
+    lines.start = lines.start.min(lines.end.saturating_sub(1));
+    lines
+}
+
+/// The part of a buffer the editor lays out, copied out for TextEdit. Edits
+/// go straight through to the buffer and its history.
+struct Window<'a> {
+    buffer: &'a mut FileBuffer,
+    /// The window's character range in the buffer.
+    chars: Range<usize>,
+    text: String,
+}
+
+impl<'a> Window<'a> {
+    fn new(buffer: &'a mut FileBuffer, chars: Range<usize>) -> Self {
+        let text = buffer.text().slice(chars.clone()).to_string();
+        Self { buffer, chars, text }
+    }
+
+    fn reload(&mut self) {
+        self.text = self.buffer.text().slice(self.chars.clone()).to_string();
+    }
+}
+
+impl TextBuffer for Window<'_> {
+    fn is_mutable(&self) -> bool {
+        true
+    }
+
+    fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    fn insert_text(&mut self, text: &str, char_index: usize) -> usize {
+        let inserted = self.buffer.insert_text(text, self.chars.start + char_index);
+        self.chars.end += inserted;
+        self.reload();
+        inserted
+    }
+
+    fn delete_char_range(&mut self, char_range: Range<usize>) {
+        let start = self.chars.start;
+        self.buffer.delete_char_range(start + char_range.start..start + char_range.end);
+        self.chars.end -= char_range.len();
+        self.reload();
+    }
+
+    fn byte_index_from_char_index(&self, char_index: usize) -> usize {
+        let text = self.buffer.text();
+        text.char_to_byte(self.chars.start + char_index) - text.char_to_byte(self.chars.start)
+    }
+
+    fn clear(&mut self) {
+        // The default counts bytes, not characters.
+        self.delete_char_range(0..self.chars.len());
+    }
+}
+
+/// Input that moves or uses the cursor, so it must be on screen first.
+fn acts_on_cursor(event: &egui::Event) -> bool {
+    matches!(
+        event,
+        egui::Event::Text(_) | egui::Event::Paste(_) | egui::Event::Cut | egui::Event::Copy | egui::Event::Key { pressed: true, .. }
+    )
+}
+
+/// Select All, and Ctrl+Home and Ctrl+End with or without Shift, which would
+/// otherwise only reach the ends of what TextEdit has laid out. Returns the
+/// new cursor, selection and where to scroll to.
+fn whole_buffer_keys(
+    ui: &Ui,
+    len_chars: usize,
+    cursor: usize,
+    selection: &Range<usize>,
+) -> Option<(usize, Range<usize>, Option<usize>)> {
+    use egui::{Key, Modifiers};
+    let anchor = if cursor == selection.start { selection.end } else { selection.start };
+    ui.input_mut(|input| {
+        if input.consume_key(Modifiers::COMMAND, Key::A) {
+            return Some((len_chars, 0..len_chars, None));
+        }
+        for (key, to) in [(Key::Home, 0), (Key::End, len_chars)] {
+            if input.consume_key(Modifiers::COMMAND | Modifiers::SHIFT, key) {
+                return Some((to, anchor.min(to)..anchor.max(to), Some(to)));
+            }
+            if input.consume_key(Modifiers::COMMAND, key) {
+                return Some((to, to..to, Some(to)));
+            }
+        }
+        None
+    })
+}
+
+/// Applies copying, cutting, typing and deleting to a selection too large to
+/// lay out, of which TextEdit holds only the part on screen. Returns where the
+/// cursor ends up when the text changed.
+fn edit_large_selection(ui: &Ui, buffer: &mut FileBuffer, selection: Range<usize>) -> Option<usize> {
+    let mut copy = false;
+    let mut replacement: Option<String> = None;
+    ui.input_mut(|input| {
+        input.events.retain(|event| {
+            match event {
+                egui::Event::Copy => copy = true,
+                egui::Event::Cut => {
+                    copy = true;
+                    replacement.get_or_insert_default();
+                }
+                egui::Event::Text(text) | egui::Event::Paste(text) => replacement.get_or_insert_default().push_str(text),
+                egui::Event::Key { key: egui::Key::Backspace | egui::Key::Delete, pressed: true, .. } => {
+                    replacement.get_or_insert_default();
+                }
+                _ => return true,
+            }
+            false
+        });
+    });
+    if copy {
+        ui.ctx().copy_text(buffer.text().slice(selection.clone()).to_string());
+    }
+    let replacement = replacement?;
+    buffer.delete_char_range(selection.clone());
+    Some(selection.start + buffer.insert_text(&replacement, selection.start))
+}
+
+/// Space either side of the line numbers.
+const GUTTER_PADDING: f32 = 6.0;
+
+/// Zero-based text line of each galley row, counting from the galley's
+/// `first_line`, and whether the row starts that line rather than continuing
+/// it after a wrap.
+fn row_lines(galley: &Galley, first_line: usize) -> impl Iterator<Item = (usize, bool, &egui::epaint::text::Row)> {
+    let mut line = first_line;
+    let mut starts_line = true;
+    galley.rows.iter().map(move |row| {
+        let item = (line, starts_line, row);
+        starts_line = row.ends_with_newline;
+        if row.ends_with_newline {
+            line += 1;
+        }
+        item
+    })
+}
+
+/// Shades every row of the cursor's line, including wrapped continuations.
+fn paint_current_line(
+    painter: &egui::Painter,
+    galley: &Galley,
+    galley_pos: Pos2,
+    clip: Rect,
+    first_line: usize,
+    cursor_line: usize,
+) {
+    let visuals = &painter.ctx().style().visuals;
+    let shade = visuals.text_color().gamma_multiply(0.06);
+    for (_, _, row) in row_lines(galley, first_line).filter(|(line, _, _)| *line == cursor_line) {
+        let top = galley_pos.y + row.rect.min.y;
+        let rect = Rect::from_x_y_ranges(clip.x_range(), top..=top + row.rect.height());
+        painter.rect_filled(rect, 0.0, shade);
+    }
+}
+
+/// Right-aligned line numbers for the rows currently on screen.
+fn paint_gutter(
+    painter: &egui::Painter,
+    galley: &Galley,
+    galley_pos: Pos2,
+    font_id: FontId,
+    first_line: usize,
+    cursor_line: usize,
+) {
+    let visuals = painter.ctx().style().visuals.clone();
+    let clip = painter.clip_rect();
+    painter.rect_filled(clip, 0.0, visuals.faint_bg_color);
+    for (line, starts_line, row) in row_lines(galley, first_line) {
+        let top = galley_pos.y + row.rect.min.y;
+        if top > clip.max.y {
+            break;
+        }
+        if !starts_line || top + row.rect.height() < clip.min.y {
+            continue;
+        }
+        let color = if line == cursor_line { visuals.strong_text_color() } else { visuals.weak_text_color() };
+        painter.text(
+            Pos2::new(clip.max.x - GUTTER_PADDING, top),
+            Align2::RIGHT_TOP,
+            line + 1,
+            font_id.clone(),
+            color,
+        );
+    }
+}
+
+const MATCH_BACKGROUND: Color32 = Color32::from_rgba_premultiplied(90, 70, 0, 90);
+const CURRENT_MATCH_BACKGROUND: Color32 = Color32::from_rgba_premultiplied(200, 120, 0, 200);
+
+/// Lays out the editor text with syntax colors and find matches, both given
+/// as byte ranges, highlighted.
+fn layout_text(
+    ui: &Ui,
+    text: &str,
+    wrap_width: f32,
+    syntax: impl IntoIterator<Item = (Range<usize>, TokenKind)>,
+    colors: &SyntaxColors,
+    matches: &[Range<usize>],
+    current: Option<usize>,
+) -> Arc<Galley> {
+    let font_id = egui::FontSelection::Default.resolve(ui.style());
+    let color = ui.visuals().override_text_color.unwrap_or_else(|| ui.visuals().widgets.inactive.text_color());
+    let plain = TextFormat::simple(font_id, color);
+
+    // Colored runs covering the whole text.
+    let mut runs = Vec::new();
+    let mut pos = 0;
+    for (range, kind) in syntax {
+        if range.start < pos || text.get(range.clone()).is_none() {
+            continue;
+        }
+        if range.start > pos {
+            runs.push((pos..range.start, color));
+        }
+        runs.push((range.clone(), colors.color(kind)));
+        pos = range.end;
+    }
+    runs.push((pos..text.len(), color));
+
+    // While typing, matches from before the edit may no longer line up with the text.
+    let mut last_end = 0;
+    let mut matches = matches
+        .iter()
+        .enumerate()
+        .filter(|(_, range)| {
+            let valid = range.start >= last_end && text.get((*range).clone()).is_some();
+            if valid {
+                last_end = range.end;
+            }
+            valid
+        })
+        .peekable();
+
+    let mut job = LayoutJob::default();
+    job.wrap.max_width = wrap_width;
+    for (range, color) in runs {
+        let mut start = range.start;
+        loop {
+            while matches.next_if(|(_, m)| m.end <= start).is_some() {}
+            let (end, background) = match matches.peek() {
+                Some((i, m)) if m.start <= start => {
+                    let background = if Some(*i) == current { CURRENT_MATCH_BACKGROUND } else { MATCH_BACKGROUND };
+                    (m.end.min(range.end), background)
+                }
+                Some((_, m)) => (m.start.min(range.end), Color32::TRANSPARENT),
+                None => (range.end, Color32::TRANSPARENT),
+            };
+            job.append(&text[start..end], 0.0, TextFormat { color, background, ..plain.clone() });
+            start = end;
+            if start >= range.end {
+                break;
+            }
+        }
+    }
+    ui.fonts(|fonts| fonts.layout_job(job))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrapped_rows_share_their_line_number() {
+        let ctx = egui::Context::default();
+        let mut lines = Vec::new();
+        let _ = ctx.run(Default::default(), |ctx| {
+            let font_id = FontId::monospace(12.0);
+            // Narrow enough that the second line wraps onto several rows.
+            let job = LayoutJob::simple("a\nbbbb bbbb bbbb\n\nc".to_string(), font_id, Color32::WHITE, 40.0);
+            let galley = ctx.fonts(|fonts| fonts.layout_job(job));
+            lines = row_lines(&galley, 0).map(|(line, starts, _)| (line, starts)).collect();
+        });
+        assert!(lines.len() > 5, "expected the second line to wrap: {:?}", lines);
+        assert_eq!(lines[0], (0, true));
+        assert_eq!(lines[1], (1, true));
+        assert!(lines[2..lines.len() - 2].iter().all(|&row| row == (1, false)));
+        assert_eq!(lines[lines.len() - 2..], [(2, true), (3, true)]);
+    }
+
+    #[test]
+    fn wrapped_long_lines_are_measured_into_the_row_counts() {
+        let ctx = egui::Context::default();
+        let mut buffer = FileBuffer::new("t", format!("top\n{}\nshort\n{}", "word ".repeat(40), "word ".repeat(40)), None);
+        let mut galley = None;
+        let _ = ctx.run(Default::default(), |ctx| {
+            let font_id = FontId::monospace(12.0);
+            // The window starts at the first long line.
+            let text = buffer.text().slice(buffer.line_to_char(1)..).to_string();
+            let job = LayoutJob::simple(text, font_id, Color32::WHITE, 100.0);
+            galley = Some(ctx.fonts(|fonts| fonts.layout_job(job)));
+        });
+        let galley = galley.unwrap();
+        let long_rows = row_lines(&galley, 1).filter(|(line, _, _)| *line == 1).count();
+        assert!(long_rows > 5, "expected the long lines to wrap onto several rows, got {}", long_rows);
+
+        // Estimated as one row per line, so every row the long lines gained
+        // above the top of the view moves it.
+        let rows = buffer.line_rows(f32::MAX, 0.0);
+        assert_eq!(rows.rows_before(4), 4);
+        let moved = measure_rows(rows, &galley, 1, 3);
+        assert_eq!(moved, long_rows as i64 - 1);
+        assert_eq!(rows.rows(1), long_rows);
+        assert_eq!(rows.rows(2), 1);
+        assert_eq!(rows.rows_before(4), 2 * long_rows + 2);
+        assert_eq!(rows.line_at_row(long_rows + 1, 4), 2);
+
+        // Laying the same lines out again changes nothing.
+        assert_eq!(measure_rows(buffer.line_rows(f32::MAX, 0.0), &galley, 1, 3), 0);
+    }
+
+    #[test]
+    fn window_covers_the_view_and_a_small_selection() {
+        // Lines on screen plus the margin, clamped to the document.
+        assert_eq!(window_lines(10_000, 100, 30, 110..111), 80..150);
+        assert_eq!(window_lines(10_000, 0, 30, 0..1), 0..50);
+        assert_eq!(window_lines(40, 0, 30, 0..1), 0..40);
+        // A selection reaching off screen is taken in with a line either side.
+        assert_eq!(window_lines(10_000, 100, 30, 50..60), 49..150);
+        // One too large to lay out is not.
+        assert_eq!(window_lines(10_000, 100, 30, 0..10_000), 80..150);
+    }
+
+    #[test]
+    fn window_edits_go_through_to_the_buffer() {
+        let mut buffer = FileBuffer::new("t", "one\nünïcödé\nthree".into(), None);
+        let second = buffer.line_to_char(1)..buffer.line_to_char(2) - 1;
+        let mut window = Window::new(&mut buffer, second);
+        assert_eq!(window.as_str(), "ünïcödé");
+        window.clear();
+        assert_eq!(window.as_str(), "");
+        window.insert_text("two", 0);
+        assert_eq!(window.as_str(), "two");
+        assert_eq!(buffer.text().to_string(), "one\ntwo\nthree");
+        buffer.undo();
+        assert_eq!(buffer.text().to_string(), "one\nünïcödé\nthree");
+    }
+}
@@ -0,0 +1,218 @@
+//! Character encodings for reading and writing files.
+//!
+//! Buffers always hold UTF-8 text; the encoding only matters at the edges,
+//! when bytes come off the disk or go back onto it.
+
+use std::borrow::Cow;
+use std::fmt;
+
+use encoding_rs::{
+    BIG5, EUC_JP, EUC_KR, Encoding, GBK, KOI8_R, SHIFT_JIS, UTF_8, UTF_16BE, UTF_16LE, WINDOWS_1250,
+    WINDOWS_1251, WINDOWS_1252,
+};
+
+/// Bytes sampled when guessing whether a file without a BOM is UTF-16.
+const SNIFF_LEN: usize = 4096;
+
+/// Encodings offered in the Reopen/Save with Encoding menus. ISO-8859-1 files
+/// are read as Windows-1252, its superset.
+pub const CHOICES: &[TextEncoding] = &[
+    TextEncoding::UTF8,
+    TextEncoding::UTF8_BOM,
+    TextEncoding { encoding: UTF_16LE, bom: true },
+    TextEncoding { encoding: UTF_16BE, bom: true },
+    TextEncoding { encoding: WINDOWS_1252, bom: false },
+    TextEncoding { encoding: WINDOWS_1250, bom: false },
+    TextEncoding { encoding: WINDOWS_1251, bom: false },
+    TextEncoding { encoding: KOI8_R, bom: false },
+    TextEncoding { encoding: SHIFT_JIS, bom: false },
+    TextEncoding { encoding: EUC_JP, bom: false },
+    TextEncoding { encoding: GBK, bom: false },
+    TextEncoding { encoding: BIG5, bom: false },
+    TextEncoding { encoding: EUC_KR, bom: false },
+];
+
+/// An encoding plus whether the file starts with a byte order mark.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TextEncoding {
+    pub encoding: &'static Encoding,
+    pub bom: bool,
+}
+
+impl Default for TextEncoding {
+    fn default() -> Self {
+        Self::UTF8
+    }
+}
+
+impl fmt::Display for TextEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if *self == Self::UTF8_BOM {
+            write!(f, "UTF-8 with BOM")
+        } else {
+            write!(f, "{}", self.encoding.name())
+        }
+    }
+}
+
+impl TextEncoding {
+    pub const UTF8: Self = Self { encoding: UTF_8, bom: false };
+    pub const UTF8_BOM: Self = Self { encoding: UTF_8, bom: true };
+
+    /// Guesses the encoding of a file's contents: a BOM wins, then UTF-16 by its
+    /// zero-byte pattern, then UTF-8 if the bytes are valid, then Shift_JIS if
+    /// it decodes cleanly into text that looks Japanese, and Windows-1252
+    /// otherwise. Text with stray NUL bytes is reported as UTF-8 so that
+    /// decoding rejects it as binary.
+    pub fn detect(bytes: &[u8]) -> Self {
+        if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+            return Self { encoding, bom: true };
+        }
+        if let Some(encoding) = sniff_utf16(bytes) {
+            return Self { encoding, bom: false };
+        }
+        if std::str::from_utf8(bytes).is_ok() {
+            return Self::UTF8;
+        }
+        if bytes.contains(&0) {
+            return Self::UTF8;
+        }
+        if SHIFT_JIS
+            .decode_without_bom_handling_and_without_replacement(bytes)
+            .is_some_and(|text| looks_japanese(&text))
+        {
+            return Self { encoding: SHIFT_JIS, bom: false };
+        }
+        Self { encoding: WINDOWS_1252, bom: false }
+    }
+
+    /// This encoding, with `bom` set to whether `bytes` start with its BOM.
+    pub fn with_bom_of(self, bytes: &[u8]) -> Self {
+        let bom = Encoding::for_bom(bytes).is_some_and(|(encoding, _)| encoding == self.encoding);
+        Self { bom, ..self }
+    }
+
+    /// Decodes file contents, skipping a BOM that matches this encoding.
+    /// `None` if the bytes are not valid in this encoding.
+    pub fn decode<'a>(&self, bytes: &'a [u8]) -> Option<Cow<'a, str>> {
+        let body = match Encoding::for_bom(bytes) {
+            Some((encoding, len)) if encoding == self.encoding => &bytes[len..],
+            _ => bytes,
+        };
+        let text = self.encoding.decode_without_bom_handling_and_without_replacement(body)?;
+        // A NUL in a text file almost always means it is binary.
+        if text.contains('\0') {
+            return None;
+        }
+        Some(text)
+    }
+
+    /// The byte order mark to write at the start of the file, if any.
+    pub fn bom_bytes(&self) -> &'static [u8] {
+        if !self.bom {
+            return &[];
+        }
+        match self.encoding.name() {
+            "UTF-8" => b"\xEF\xBB\xBF",
+            "UTF-16LE" => b"\xFF\xFE",
+            "UTF-16BE" => b"\xFE\xFF",
+            _ => &[],
+        }
+    }
+
+    /// Encodes a piece of text. `None` if it contains characters this encoding
+    /// cannot represent.
+    pub fn encode<'a>(&self, text: &'a str) -> Option<Cow<'a, [u8]>> {
+        if self.encoding == UTF_16LE {
+            return Some(Cow::Owned(text.encode_utf16().flat_map(u16::to_le_bytes).collect()));
+        }
+        if self.encoding == UTF_16BE {
+            return Some(Cow::Owned(text.encode_utf16().flat_map(u16::to_be_bytes).collect()));
+        }
+        let (bytes, _, unmappable) = self.encoding.encode(text);
+        if unmappable { None } else { Some(bytes) }
+    }
+}
+
+/// Whether text decoded as Shift_JIS reads like Japanese rather than Latin-1
+/// bytes that happen to decode: "Mädchen" comes out as "M臈chen" and "Straße"
+/// as "Straﾟe". Japanese prose is full of kana and full-width punctuation,
+/// which Latin-1 text almost never produces, so at least a fifth of the
+/// non-ASCII characters must be one of those.
+fn looks_japanese(text: &str) -> bool {
+    let mut non_ascii = 0;
+    let mut kana = 0;
+    for c in text.chars().filter(|c| !c.is_ascii()) {
+        non_ascii += 1;
+        // Full-width punctuation, hiragana and katakana, and full-width forms.
+        if matches!(c, '\u{3000}'..='\u{30FF}' | '\u{FF01}'..='\u{FF5E}') {
+            kana += 1;
+        }
+    }
+    kana > 0 && kana * 5 >= non_ascii
+}
+
+/// UTF-16 text that is mostly ASCII has a zero in every other byte.
+fn sniff_utf16(bytes: &[u8]) -> Option<&'static Encoding> {
+    let sample = &bytes[..bytes.len().min(SNIFF_LEN)];
+    if sample.len() < 2 || !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let pairs = sample.len() / 2;
+    let even_zeros = sample.iter().step_by(2).filter(|&&b| b == 0).count();
+    let odd_zeros = sample.iter().skip(1).step_by(2).filter(|&&b| b == 0).count();
+    if odd_zeros * 10 >= pairs * 4 && even_zeros * 10 < pairs {
+        Some(UTF_16LE)
+    } else if even_zeros * 10 >= pairs * 4 && odd_zeros * 10 < pairs {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(encoding: TextEncoding, text: &str) -> Vec<u8> {
+        let mut bytes = encoding.bom_bytes().to_vec();
+        bytes.extend_from_slice(&encoding.encode(text).unwrap());
+        assert_eq!(TextEncoding::detect(&bytes), encoding);
+        assert_eq!(encoding.decode(&bytes).unwrap(), text);
+        bytes
+    }
+
+    #[test]
+    fn detects_boms_and_keeps_them() {
+        let bytes = round_trip(TextEncoding::UTF8_BOM, "héllo");
+        assert!(bytes.starts_with(b"\xEF\xBB\xBF"));
+        round_trip(TextEncoding { encoding: UTF_16LE, bom: true }, "héllo wörld");
+        round_trip(TextEncoding { encoding: UTF_16BE, bom: true }, "héllo wörld");
+        round_trip(TextEncoding::UTF8, "plain ascii");
+    }
+
+    #[test]
+    fn detects_legacy_encodings() {
+        round_trip(TextEncoding { encoding: UTF_16LE, bom: false }, "no bom here");
+        round_trip(TextEncoding { encoding: SHIFT_JIS, bom: false }, "こんにちは、世界");
+        round_trip(TextEncoding { encoding: WINDOWS_1252, bom: false }, "café – déjà vu");
+    }
+
+    #[test]
+    fn latin1_text_is_not_mistaken_for_shift_jis() {
+        let windows_1252 = TextEncoding { encoding: WINDOWS_1252, bom: false };
+        // The first two also decode cleanly as Shift_JIS.
+        for text in ["Straße", "Mädchen", "Grüße aus München", "Où est la forêt ? Très bien, garçon."] {
+            round_trip(windows_1252, text);
+        }
+    }
+
+    #[test]
+    fn rejects_binary_and_unencodable_text() {
+        // The start of an ELF executable.
+        let binary = [0x7f, b'E', b'L', b'F', 0x02, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff];
+        let guess = TextEncoding::detect(&binary);
+        assert!(guess.decode(&binary).is_none());
+        assert!(TextEncoding { encoding: WINDOWS_1252, bom: false }.encode("日本").is_none());
+    }
+}
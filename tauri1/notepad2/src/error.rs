@@ -0,0 +1,63 @@
+//! Errors from opening and saving buffers, worded for the status bar.
+
+use std::fmt;
+use std::io::{self, ErrorKind};
+
+/// The `Option<String>` on the classified I/O errors is the step that failed,
+/// e.g. `could not create temporary file …`, when the error came from a save.
+#[derive(Debug)]
+pub enum BufferError {
+    NotFound(String, Option<String>),
+    PermissionDenied(String, Option<String>),
+    /// The file is not text in the given encoding, or is binary.
+    InvalidText(String, &'static str),
+    /// The buffer has never been saved, so there is nowhere to write it.
+    NoPath,
+    DiskFull(String, Option<String>),
+    /// The text has characters the chosen encoding cannot represent.
+    Unencodable(String, &'static str),
+    Io(String, io::Error),
+}
+
+impl BufferError {
+    /// Classifies an I/O error from reading or writing `path`.
+    pub fn from_io(path: &str, err: io::Error) -> Self {
+        let path = path.to_string();
+        // `atomic_write` wraps each failure with the step it was taking.
+        let step = err.get_ref().map(|inner| inner.to_string());
+        match err.kind() {
+            ErrorKind::NotFound => Self::NotFound(path, step),
+            ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem => Self::PermissionDenied(path, step),
+            ErrorKind::StorageFull | ErrorKind::QuotaExceeded => Self::DiskFull(path, step),
+            _ => Self::Io(path, err),
+        }
+    }
+}
+
+impl fmt::Display for BufferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(path, Some(step)) | Self::PermissionDenied(path, Some(step)) | Self::DiskFull(path, Some(step)) => {
+                write!(f, "{}: {}", path, step)
+            }
+            Self::NotFound(path, None) => write!(f, "{}: file or folder not found", path),
+            Self::PermissionDenied(path, None) => write!(f, "{}: permission denied", path),
+            Self::InvalidText(path, encoding) => write!(f, "{}: not valid {} text", path, encoding),
+            Self::NoPath => write!(f, "no file path set"),
+            Self::DiskFull(path, None) => write!(f, "{}: disk full", path),
+            Self::Unencodable(path, encoding) => {
+                write!(f, "{}: contains characters that {} cannot represent", path, encoding)
+            }
+            Self::Io(path, err) => write!(f, "{}: {}", path, err),
+        }
+    }
+}
+
+impl std::error::Error for BufferError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
@@ -1,71 +1,891 @@
 // src/file_buffer.rs
+use std::borrow::Cow;
 use std::fs;
-use std::io::{self, Write};
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{Duration, Instant};
-use std::path::PathBuf;
 
+use eframe::egui::Vec2;
+use ropey::{Rope, RopeSlice};
+
+use crate::atomic_write::{self, BackupMode};
+use crate::encoding::TextEncoding;
+use crate::error::BufferError;
+use crate::highlight::{Highlighter, LineState};
+use crate::history::{EditGroup, EditOp, History};
+use crate::language::Language;
+use crate::line_ending::{self, LineEnding, LineEndings};
+use crate::line_rows::LineRows;
+use crate::search::{Match, Matcher};
+
+/// Typing after a pause this long starts a new undo step.
+const UNDO_GROUP_PAUSE: Duration = Duration::from_millis(1000);
+
+/// Characters of the first line read when looking for a `#!` interpreter.
+const SHEBANG_LEN: usize = 128;
+
+static NEXT_BUFFER_ID: AtomicU64 = AtomicU64::new(1);
+
+/// An open document. The text lives in a [`Rope`], so edits and line lookups
+/// stay O(log n) however large the file is. Nothing keeps a flat copy of it:
+/// the editor copies out only the lines on screen, and search and save walk
+/// the rope's chunks.
 #[derive(Clone)]
 pub struct FileBuffer {
+    /// Unique within this process; names the buffer's recovery swap file.
+    pub id: u64,
     pub name: String,
-    pub content: String,
+    text: Rope,
     pub file_path: Option<String>,
-    pub undo_stack: Vec<String>,
-    pub redo_stack: Vec<String>,
+    /// How the text is stored on disk.
+    pub encoding: TextEncoding,
+    pub line_endings: LineEndings,
+    /// Language picked by hand, overriding detection.
+    pub language_override: Option<Language>,
+    /// Line endings of the file on disk; a conversion leaves the buffer modified.
+    saved_line_endings: LineEndings,
+    pub history: History,
+    /// History revision that matches the file on disk.
+    saved_revision: u64,
+    /// Cursor position the editor should jump to on the next frame, after undo/redo.
+    pub pending_cursor: Option<usize>,
+    /// The editor's selection as a sorted character range; empty when nothing is selected.
+    pub selection: Range<usize>,
+    /// Character range the editor should select and scroll into view on the next frame.
+    pub pending_selection: Option<Range<usize>>,
+    /// Where the editor last left the cursor and scroll position, kept per tab
+    /// and carried over between sessions.
+    pub cursor: usize,
+    pub scroll: Vec2,
+    /// Scroll offset the editor should jump to on the next frame, e.g. after a session restore.
+    pub pending_scroll: Option<Vec2>,
     pub last_edit_time: Instant,
+    version: u64,
+    /// Words in the text, adjusted by each edit for the lines it touches.
+    words: usize,
+    highlighter: Highlighter,
+    rows: LineRows,
 }
 
 impl FileBuffer {
     pub fn new(name: &str, content: String, path: Option<String>) -> Self {
+        let text = Rope::from_str(&content);
         Self {
+            id: NEXT_BUFFER_ID.fetch_add(1, Ordering::Relaxed),
             name: name.to_string(),
-            content,
+            words: count_words(text.slice(..)),
+            text,
             file_path: path,
-            undo_stack: Vec::new(),
-            redo_stack: Vec::new(),
+            encoding: TextEncoding::UTF8,
+            line_endings: LineEndings::default(),
+            language_override: None,
+            saved_line_endings: LineEndings::default(),
+            history: History::default(),
+            saved_revision: 0,
+            pending_cursor: None,
+            selection: 0..0,
+            pending_selection: None,
+            cursor: 0,
+            scroll: Vec2::ZERO,
+            pending_scroll: None,
             last_edit_time: Instant::now(),
+            version: 0,
+            highlighter: Highlighter::default(),
+            rows: LineRows::default(),
         }
     }
 
-    pub fn push_undo(&mut self) {
-        if self.undo_stack.last().map_or(true, |last| last != &self.content) {
-            self.undo_stack.push(self.content.clone());
+    /// Reads a file from disk into a new buffer named after the file, guessing its encoding.
+    pub fn open(path: &Path) -> Result<Self, BufferError> {
+        Self::open_with(path, None)
+    }
+
+    /// Like [`Self::open`], but decodes as `encoding` when one is given.
+    pub fn open_with(path: &Path, encoding: Option<TextEncoding>) -> Result<Self, BufferError> {
+        let display = path.display().to_string();
+        let bytes = fs::read(path).map_err(|err| BufferError::from_io(&display, err))?;
+        let encoding = match encoding {
+            Some(encoding) => encoding.with_bom_of(&bytes),
+            None => TextEncoding::detect(&bytes),
+        };
+        let decoded = encoding
+            .decode(&bytes)
+            .ok_or(BufferError::InvalidText(display.clone(), encoding.encoding.name()))?;
+        let (contents, line_endings) = LineEndings::normalize(&decoded);
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let mut buffer = Self::new(&name, contents.into_owned(), Some(display));
+        buffer.encoding = encoding;
+        buffer.line_endings = line_endings;
+        buffer.saved_line_endings = line_endings;
+        Ok(buffer)
+    }
+
+    /// Reads the file again, decoding it as `encoding`. Undo history starts over,
+    /// so this is only meant for unmodified buffers. The tab keeps its name,
+    /// language, cursor, selection and scroll position.
+    pub fn reopen_with(&mut self, encoding: TextEncoding) -> Result<(), BufferError> {
+        let path = self.file_path.clone().ok_or(BufferError::NoPath)?;
+        let reopened = Self::open_with(Path::new(&path), Some(encoding))?;
+        let len = reopened.len_chars();
+        *self = Self {
+            id: self.id,
+            name: std::mem::take(&mut self.name),
+            language_override: self.language_override,
+            cursor: self.cursor.min(len),
+            selection: self.selection.start.min(len)..self.selection.end.min(len),
+            scroll: self.scroll,
+            pending_scroll: Some(self.scroll),
+            version: self.version + 1,
+            ..reopened
+        };
+        Ok(())
+    }
+
+    pub fn text(&self) -> &Rope {
+        &self.text
+    }
+
+    /// Each line, line break included, with the byte offset it starts at. A
+    /// line that sits in one rope chunk is borrowed rather than copied.
+    pub fn lines(&self) -> impl Iterator<Item = (usize, Cow<'_, str>)> {
+        self.text.lines().scan(0, |offset, line| {
+            let start = *offset;
+            let line = Cow::from(line);
+            *offset += line.len();
+            Some((start, line))
+        })
+    }
+
+    /// Byte ranges of every match. Lines are searched one at a time, so a
+    /// match never spans a line break.
+    pub fn find_all(&self, matcher: &Matcher) -> Vec<Range<usize>> {
+        self.lines()
+            .flat_map(|(offset, line)| {
+                matcher.find_all(&line).into_iter().map(move |range| range.start + offset..range.end + offset)
+            })
+            .collect()
+    }
+
+    pub fn len_chars(&self) -> usize {
+        self.text.len_chars()
+    }
+
+    // Line indexing for the editor views; the gutter and go-to-line are the
+    // intended callers.
+    pub fn char_to_byte(&self, char_index: usize) -> usize {
+        self.text.char_to_byte(char_index.min(self.text.len_chars()))
+    }
+
+    pub fn byte_to_char(&self, byte_index: usize) -> usize {
+        self.text.byte_to_char(byte_index.min(self.text.len_bytes()))
+    }
+
+    /// Changes every time the text does, so views can cache work derived from it.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn len_lines(&self) -> usize {
+        self.text.len_lines()
+    }
+
+    /// Runs of non-whitespace. Kept up to date as the text is edited, so this is free to call.
+    pub fn word_count(&self) -> usize {
+        self.words
+    }
+
+    /// Words on the zero-based lines `first..=last`. A line break is
+    /// whitespace, so no word spans two lines.
+    fn words_on_lines(&self, first: usize, last: usize) -> usize {
+        let start = self.text.line_to_char(first);
+        let end = self.text.line_to_char((last + 1).min(self.text.len_lines()));
+        count_words(self.text.slice(start..end))
+    }
+
+    /// The chosen language mode, or the one detected from the path and first line.
+    pub fn language(&self) -> Language {
+        if let Some(language) = self.language_override {
+            return language;
         }
+        let first_line = self.text.line(0);
+        let first_line = first_line.slice(..first_line.len_chars().min(SHEBANG_LEN));
+        Language::detect(self.file_path.as_deref(), &first_line.to_string())
+    }
+
+    /// Zero-based line containing the given character index.
+    pub fn char_to_line(&self, char_index: usize) -> usize {
+        self.text.char_to_line(char_index.min(self.text.len_chars()))
+    }
+
+    /// Character index of the start of a zero-based line.
+    pub fn line_to_char(&self, line: usize) -> usize {
+        self.text.line_to_char(line.min(self.text.len_lines()))
     }
 
     pub fn undo(&mut self) {
-        if let Some(prev) = self.undo_stack.pop() {
-            self.redo_stack.push(self.content.clone());
-            self.content = prev;
+        if let Some(group) = self.history.take_undo() {
+            for op in group.ops.iter().rev() {
+                self.apply(&op.inverse());
+            }
+            self.pending_cursor = Some(group.cursor_before);
         }
     }
 
     pub fn redo(&mut self) {
-        if let Some(next) = self.redo_stack.pop() {
-            self.undo_stack.push(self.content.clone());
-            self.content = next;
+        if let Some(group) = self.history.take_redo() {
+            for op in &group.ops {
+                self.apply(op);
+            }
+            self.pending_cursor = Some(group.cursor_after);
         }
     }
 
-    pub fn needs_autosave(&self) -> bool {
-        self.last_edit_time.elapsed() > Duration::from_secs(10)
+    /// Replaces the whole content as a single undo step.
+    pub fn set_content(&mut self, text: &str) {
+        let ops = [
+            EditOp::Delete { at: 0, text: self.text.to_string() },
+            EditOp::Insert { at: 0, text: text.to_string() },
+        ];
+        let ops = ops.into_iter().filter(|op| !op.text().is_empty()).collect();
+        self.apply_group(ops, 0);
+    }
+
+    /// Replaces every match, or every match inside the character range `within`,
+    /// as a single undo step. Returns the number of replacements.
+    pub fn replace_all(&mut self, matcher: &Matcher, replace: &str, within: Option<Range<usize>>) -> usize {
+        let bounds = within.map(|chars| self.char_to_byte(chars.start)..self.char_to_byte(chars.end));
+        let matches = self
+            .lines()
+            .flat_map(|(offset, line)| {
+                matcher.replacements(&line, replace).into_iter().map(move |m| Match {
+                    range: m.range.start + offset..m.range.end + offset,
+                    replacement: m.replacement,
+                })
+            })
+            .filter(|m| bounds.as_ref().is_none_or(|b| b.start <= m.range.start && m.range.end <= b.end))
+            .collect();
+        self.replace_matches(matches)
+    }
+
+    /// Replaces the match at the byte range `range` as a single undo step.
+    /// Returns `false` if the text there no longer matches.
+    pub fn replace_match(&mut self, matcher: &Matcher, range: Range<usize>, replace: &str) -> bool {
+        if range.end > self.text.len_bytes() {
+            return false;
+        }
+        let line = self.text.byte_to_line(range.start);
+        let offset = self.text.line_to_byte(line);
+        let text = Cow::from(self.text.line(line));
+        match matcher.replacement_at(&text, range.start - offset..range.end - offset, replace) {
+            Some(replacement) => self.replace_matches(vec![Match { range, replacement }]) == 1,
+            None => false,
+        }
+    }
+
+    fn replace_matches(&mut self, matches: Vec<Match>) -> usize {
+        let Some(first) = matches.first() else {
+            return 0;
+        };
+        let first = self.text.byte_to_char(first.range.start);
+
+        // Back to front, so earlier positions are unaffected by later replacements.
+        let mut ops = Vec::with_capacity(matches.len() * 2);
+        for m in matches.iter().rev() {
+            let at = self.text.byte_to_char(m.range.start);
+            let found = self.text.byte_slice(m.range.clone()).to_string();
+            ops.push(EditOp::Delete { at, text: found });
+            if !m.replacement.is_empty() {
+                ops.push(EditOp::Insert { at, text: m.replacement.clone() });
+            }
+        }
+        self.apply_group(ops, first);
+        matches.len()
+    }
+
+    /// Applies a prepared list of operations and records them as one undo step.
+    fn apply_group(&mut self, ops: Vec<EditOp>, cursor_before: usize) {
+        for op in &ops {
+            self.apply(op);
+        }
+        let cursor_after = ops.last().map_or(cursor_before, EditOp::end_cursor);
+        self.history.push_group(EditGroup { ops, cursor_before, cursor_after, ..Default::default() });
+        self.last_edit_time = Instant::now();
+    }
+
+    /// Inserts typed or pasted text and returns the number of characters
+    /// inserted, which differs from `text` when its line endings are converted.
+    /// Every edit is recorded in the history.
+    pub fn insert_text(&mut self, text: &str, char_index: usize) -> usize {
+        // Pasted text may bring its own line endings.
+        let text = line_ending::normalize_str(text);
+        if text.is_empty() {
+            return 0;
+        }
+        let count = text.chars().count();
+        self.record(EditOp::Insert { at: char_index, text: text.into_owned() });
+        count
+    }
+
+    pub fn delete_char_range(&mut self, char_range: Range<usize>) {
+        assert!(char_range.start <= char_range.end);
+        if char_range.is_empty() {
+            return;
+        }
+        let text = self.text.slice(char_range.clone()).to_string();
+        self.record(EditOp::Delete { at: char_range.start, text });
+    }
+
+    /// Applies an operation to the content and records it, closing the open
+    /// undo step first if the user paused since the previous edit.
+    fn record(&mut self, op: EditOp) {
+        if self.last_edit_time.elapsed() > UNDO_GROUP_PAUSE {
+            self.history.commit();
+        }
+        self.apply(&op);
+        self.history.record(op);
+        self.last_edit_time = Instant::now();
+    }
+
+    /// Applies an operation without recording it in the history.
+    fn apply(&mut self, op: &EditOp) {
+        self.version += 1;
+        match op {
+            EditOp::Insert { at, text } => {
+                let line = self.text.char_to_line(*at);
+                let before = self.words_on_lines(line, line);
+                self.text.insert(*at, text);
+                let added = self.text.char_to_line(at + text.chars().count()) - line;
+                self.words = self.words - before + self.words_on_lines(line, line + added);
+                self.highlighter.edited(line, 0, added);
+                self.rows.edited(&self.text, line, 0, added);
+            }
+            EditOp::Delete { at, text } => {
+                let end = at + text.chars().count();
+                let line = self.text.char_to_line(*at);
+                let removed = self.text.char_to_line(end) - line;
+                let before = self.words_on_lines(line, line + removed);
+                self.text.remove(*at..end);
+                self.words = self.words - before + self.words_on_lines(line, line);
+                self.highlighter.edited(line, removed, 0);
+                self.rows.edited(&self.text, line, removed, 0);
+            }
+        }
+    }
+
+    /// Where syntax highlighting picks up at the start of a zero-based line.
+    /// `None` for plain text, and when parsing the lines before it runs past
+    /// `deadline`; the next call carries on from there.
+    pub fn highlight_state(&mut self, line: usize, deadline: Instant) -> Option<LineState> {
+        let language = self.language();
+        self.highlighter.state_at(language, &self.text, line, deadline)
+    }
+
+    /// Rows each line takes in the editor when wrapped at `wrap_width`, or one
+    /// each when that is infinite.
+    pub fn line_rows(&mut self, wrap_width: f32, char_width: f32) -> &mut LineRows {
+        self.rows.fit(&self.text, wrap_width, char_width);
+        &mut self.rows
+    }
+
+    /// The row counts as last fitted by [`Self::line_rows`], kept up to date by edits since.
+    pub fn rows(&self) -> &LineRows {
+        &self.rows
+    }
+
+    /// Whether this buffer has unsaved edits older than `idle` and a file to save them to.
+    pub fn needs_autosave(&self, idle: Duration) -> bool {
+        self.is_dirty() && self.file_path.is_some() && self.last_edit_time.elapsed() >= idle
     }
 
     pub fn reset_edit_timer(&mut self) {
         self.last_edit_time = Instant::now();
     }
 
-    pub fn save(&mut self) -> io::Result<()> {
-        if let Some(path) = &self.file_path {
-            fs::write(path, &self.content)?;
-            self.mark_clean();
-            Ok(())
-        } else {
-            // Could show a "Save As" dialog in future
-            Err(io::Error::new(io::ErrorKind::Other, "No file path set"))
+    /// Replaces the file at `path` with the content in the buffer's encoding,
+    /// written chunk by chunk without flattening the rope.
+    pub fn write_to(&self, path: &str, backup: BackupMode) -> Result<(), BufferError> {
+        let chunks = self.text.chunks().map(|chunk| self.line_endings.style.apply(chunk));
+        write_encoded(path, backup, &self.encoding, chunks)
+    }
+
+    /// Replaces every match in the file at `path` and writes it back without
+    /// opening it in a buffer. The encoding and each line's own line ending
+    /// are kept, so a file with mixed endings changes only where it matched.
+    /// Lines are matched as a buffer would hold them, ending in `\n`. Returns
+    /// the number of replacements; the file is not written when there are none.
+    pub fn replace_in_file(path: &Path, matcher: &Matcher, replace: &str, backup: BackupMode) -> Result<usize, BufferError> {
+        let display = path.display().to_string();
+        let bytes = fs::read(path).map_err(|err| BufferError::from_io(&display, err))?;
+        let encoding = TextEncoding::detect(&bytes);
+        let decoded = encoding
+            .decode(&bytes)
+            .ok_or(BufferError::InvalidText(display.clone(), encoding.encoding.name()))?;
+        let mut count = 0;
+        let mut replaced = String::with_capacity(decoded.len());
+        for (line, ending) in line_ending::split_lines(&decoded) {
+            let line = if ending.is_empty() { Cow::Borrowed(line) } else { Cow::Owned(format!("{}\n", line)) };
+            let mut pos = 0;
+            let mut out = String::new();
+            for m in matcher.replacements(&line, replace) {
+                out.push_str(&line[pos..m.range.start]);
+                out.push_str(&m.replacement);
+                pos = m.range.end;
+                count += 1;
+            }
+            out.push_str(&line[pos..]);
+            match out.strip_suffix('\n') {
+                Some(text) if !ending.is_empty() => {
+                    replaced.push_str(text);
+                    replaced.push_str(ending);
+                }
+                _ => replaced.push_str(&out),
+            }
+        }
+        if count > 0 {
+            write_encoded(&display, backup, &encoding, [Cow::Owned(replaced)])?;
         }
+        Ok(count)
+    }
+
+    /// Saves to the buffer's own path. Untitled buffers fail with
+    /// [`BufferError::NoPath`]; the caller decides whether to ask for one.
+    pub fn save(&mut self, backup: BackupMode) -> Result<(), BufferError> {
+        let Some(path) = &self.file_path else {
+            return Err(BufferError::NoPath);
+        };
+        self.write_to(path, backup)?;
+        self.mark_clean();
+        Ok(())
     }
 
     pub fn mark_clean(&mut self) {
+        // Close the open undo step so further typing gets a new revision.
+        self.history.commit();
+        self.saved_revision = self.history.revision();
+        // The file was written with one style throughout.
+        self.line_endings.mixed = false;
+        self.saved_line_endings = self.line_endings;
         self.last_edit_time = std::time::Instant::now();
     }
-} 
\ No newline at end of file
+
+    /// Whether the content differs from what was last loaded or saved.
+    pub fn is_dirty(&self) -> bool {
+        self.history.revision() != self.saved_revision || self.line_endings != self.saved_line_endings
+    }
+
+    /// Switches the style used when saving. The text itself is unchanged.
+    pub fn set_line_ending(&mut self, style: LineEnding) {
+        self.line_endings = LineEndings { style, mixed: false };
+    }
+}
+
+/// Writes `chunks` to `path` in `encoding`, after its byte order mark if it has one.
+fn write_encoded<'a>(
+    path: &str,
+    backup: BackupMode,
+    encoding: &TextEncoding,
+    chunks: impl IntoIterator<Item = Cow<'a, str>>,
+) -> Result<(), BufferError> {
+    let mut unencodable = false;
+    let result = atomic_write::write_file(Path::new(path), backup, |writer| {
+        writer.write_all(encoding.bom_bytes())?;
+        for chunk in chunks {
+            let Some(bytes) = encoding.encode(&chunk) else {
+                unencodable = true;
+                return Err(io::Error::other("unencodable text"));
+            };
+            writer.write_all(&bytes)?;
+        }
+        Ok(())
+    });
+    match result {
+        Ok(()) => Ok(()),
+        Err(_) if unencodable => Err(BufferError::Unencodable(path.to_string(), encoding.encoding.name())),
+        Err(err) => Err(BufferError::from_io(path, err)),
+    }
+}
+
+/// Runs of non-whitespace in `text`.
+fn count_words(text: RopeSlice) -> usize {
+    let mut words = 0;
+    let mut in_word = false;
+    for c in text.chars() {
+        let word_char = !c.is_whitespace();
+        if word_char && !in_word {
+            words += 1;
+        }
+        in_word = word_char;
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::SearchOptions;
+
+    fn large_text() -> String {
+        "2024-01-01 12:00:00 INFO request handled in 3ms — ok\n".repeat(100_000)
+    }
+
+    #[test]
+    fn undo_redo_round_trip_on_large_buffer() {
+        let original = large_text();
+        let mut buffer = FileBuffer::new("log", original.clone(), None);
+
+        buffer.insert_text("héllo ", 10);
+        buffer.history.commit();
+        buffer.delete_char_range(500..2_000);
+        buffer.history.commit();
+        let end = buffer.len_chars();
+        buffer.insert_text("\ntail", end);
+        buffer.history.commit();
+        let edited = buffer.text().to_string();
+
+        buffer.undo();
+        buffer.undo();
+        buffer.undo();
+        assert_eq!(buffer.text().to_string(), original);
+        assert_eq!(buffer.pending_cursor, Some(10));
+
+        buffer.redo();
+        buffer.redo();
+        buffer.redo();
+        assert_eq!(buffer.text().to_string(), edited);
+    }
+
+    #[test]
+    fn history_memory_tracks_edits_not_file_size() {
+        let mut buffer = FileBuffer::new("log", large_text(), None);
+        for i in 0..1_000 {
+            buffer.insert_text("x", i);
+            buffer.history.commit();
+        }
+        assert_eq!(buffer.history.memory_usage(), 1_000);
+    }
+
+    #[test]
+    fn consecutive_inserts_within_a_step_merge() {
+        let mut buffer = FileBuffer::new("t", String::new(), None);
+        buffer.insert_text("a", 0);
+        buffer.insert_text("b", 1);
+        buffer.insert_text("c", 2);
+        buffer.history.commit();
+        buffer.undo();
+        assert_eq!(buffer.text().to_string(), "");
+        assert!(!buffer.history.can_undo());
+        buffer.redo();
+        assert_eq!(buffer.text().to_string(), "abc");
+        assert_eq!(buffer.pending_cursor, Some(3));
+    }
+
+    #[test]
+    fn new_edit_discards_redo() {
+        let mut buffer = FileBuffer::new("t", "abc".into(), None);
+        buffer.delete_char_range(1..2);
+        buffer.history.commit();
+        buffer.undo();
+        assert!(buffer.history.can_redo());
+        buffer.insert_text("z", 0);
+        assert!(!buffer.history.can_redo());
+        assert_eq!(buffer.text().to_string(), "zabc");
+    }
+
+    fn literal(query: &str) -> Matcher {
+        Matcher::new(query, SearchOptions { match_case: true, ..Default::default() }).unwrap()
+    }
+
+    fn type_text(buffer: &mut FileBuffer, text: &str) {
+        for c in text.chars() {
+            let at = buffer.len_chars();
+            buffer.insert_text(&c.to_string(), at);
+        }
+    }
+
+    #[test]
+    fn typing_groups_by_word() {
+        let mut buffer = FileBuffer::new("t", String::new(), None);
+        type_text(&mut buffer, "hello brave world");
+        buffer.undo();
+        assert_eq!(buffer.text().to_string(), "hello brave ");
+        buffer.undo();
+        assert_eq!(buffer.text().to_string(), "hello ");
+        buffer.undo();
+        assert_eq!(buffer.text().to_string(), "");
+    }
+
+    #[test]
+    fn pause_starts_new_group() {
+        let mut buffer = FileBuffer::new("t", String::new(), None);
+        type_text(&mut buffer, "ab");
+        buffer.last_edit_time = Instant::now() - Duration::from_secs(5);
+        type_text(&mut buffer, "cd");
+        buffer.undo();
+        assert_eq!(buffer.text().to_string(), "ab");
+    }
+
+    #[test]
+    fn cursor_jump_starts_new_group() {
+        let mut buffer = FileBuffer::new("t", "0123456789".into(), None);
+        buffer.insert_text("a", 10);
+        buffer.insert_text("b", 2);
+        buffer.undo();
+        assert_eq!(buffer.text().to_string(), "0123456789a");
+        assert_eq!(buffer.pending_cursor, Some(2));
+    }
+
+    #[test]
+    fn backspace_run_is_one_step_and_separate_from_typing() {
+        let mut buffer = FileBuffer::new("t", String::new(), None);
+        type_text(&mut buffer, "word");
+        for end in (1..=4).rev() {
+            buffer.delete_char_range(end - 1..end);
+        }
+        assert_eq!(buffer.text().to_string(), "");
+        buffer.undo();
+        assert_eq!(buffer.text().to_string(), "word");
+        buffer.undo();
+        assert_eq!(buffer.text().to_string(), "");
+    }
+
+    #[test]
+    fn paste_over_selection_is_its_own_step() {
+        let mut buffer = FileBuffer::new("t", String::new(), None);
+        type_text(&mut buffer, "abc");
+        buffer.delete_char_range(0..3);
+        buffer.insert_text("pasted", 0);
+        type_text(&mut buffer, "x");
+        buffer.undo();
+        assert_eq!(buffer.text().to_string(), "pasted");
+        buffer.undo();
+        assert_eq!(buffer.text().to_string(), "abc");
+    }
+
+    #[test]
+    fn replace_all_is_one_step_and_counts() {
+        let mut buffer = FileBuffer::new("t", "foo bär foo".into(), None);
+        type_text(&mut buffer, "!");
+        assert_eq!(buffer.replace_all(&literal("foo"), "bäz", None), 2);
+        assert_eq!(buffer.text().to_string(), "bäz bär bäz!");
+        buffer.undo();
+        assert_eq!(buffer.text().to_string(), "foo bär foo!");
+        buffer.redo();
+        assert_eq!(buffer.text().to_string(), "bäz bär bäz!");
+        assert_eq!(buffer.replace_all(&literal("missing"), "x", None), 0);
+    }
+
+    #[test]
+    fn replace_in_selection_and_single_match() {
+        let mut buffer = FileBuffer::new("t", "a a a a".into(), None);
+        assert_eq!(buffer.replace_all(&literal("a"), "b", Some(2..5)), 2);
+        assert_eq!(buffer.text().to_string(), "a b b a");
+
+        assert!(buffer.replace_match(&literal("a"), 6..7, "c"));
+        assert_eq!(buffer.text().to_string(), "a b b c");
+        // The text at the range has changed, so there is nothing to replace.
+        assert!(!buffer.replace_match(&literal("a"), 6..7, "d"));
+        buffer.undo();
+        assert_eq!(buffer.text().to_string(), "a b b a");
+        buffer.undo();
+        assert_eq!(buffer.text().to_string(), "a a a a");
+    }
+
+    #[test]
+    fn lines_and_search_read_the_rope_in_place() {
+        let mut buffer = FileBuffer::new("t", "line one\nline two\n".into(), None);
+        buffer.insert_text("ü", 5);
+        buffer.delete_char_range(0..2);
+        let lines: Vec<(usize, String)> = buffer.lines().map(|(offset, line)| (offset, line.into_owned())).collect();
+        assert_eq!(lines, vec![(0, "ne üone\n".into()), (9, "line two\n".into()), (18, String::new())]);
+        assert_eq!(buffer.find_all(&literal("ne")), vec![0..2, 6..8, 11..13]);
+        assert_eq!(buffer.char_to_line(12), 1);
+        assert_eq!(buffer.line_to_char(1), 8);
+        assert_eq!(buffer.word_count(), 4);
+        buffer.undo();
+        buffer.undo();
+        assert_eq!(buffer.text().to_string(), "line one\nline two\n");
+
+        // Long text spans many rope chunks; matches inside and across them are found.
+        let long = FileBuffer::new("log", large_text(), None);
+        assert!(long.text().chunks().count() > 1);
+        assert_eq!(long.find_all(&literal("INFO")).len(), 100_000);
+    }
+
+    #[test]
+    fn word_count_follows_edits_across_lines() {
+        let mut buffer = FileBuffer::new("t", "one two\nthree\n\nfour five".into(), None);
+        assert_eq!(buffer.word_count(), 5);
+        // Joining lines merges the words on either side of the break.
+        buffer.delete_char_range(7..8);
+        assert_eq!(buffer.word_count(), 4);
+        // Splitting a word in two, and pasting several lines at once.
+        buffer.insert_text(" ", 2);
+        assert_eq!(buffer.word_count(), 5);
+        buffer.insert_text("six\nseven eight\nni", 0);
+        assert_eq!(buffer.word_count(), 8);
+        buffer.delete_char_range(4..buffer.len_chars() - 4);
+        assert_eq!(buffer.word_count(), count_words(buffer.text().slice(..)));
+        buffer.undo();
+        buffer.undo();
+        buffer.undo();
+        buffer.undo();
+        assert_eq!(buffer.text().to_string(), "one two\nthree\n\nfour five");
+        assert_eq!(buffer.word_count(), 5);
+    }
+
+    #[test]
+    fn highlight_state_follows_edits_and_undo() {
+        let later = Instant::now() + Duration::from_secs(60);
+        // Where a line starts outside any comment.
+        let outside = FileBuffer::new("t.rs", "a\nb\nc\n".into(), Some("t.rs".into())).highlight_state(2, later);
+        assert!(outside.is_some());
+        let mut buffer = FileBuffer::new("t.rs", "/* a\nb */\nc\n".into(), Some("t.rs".into()));
+        assert_eq!(buffer.highlight_state(2, later), outside);
+        buffer.delete_char_range(7..9);
+        assert_ne!(buffer.highlight_state(2, later), outside);
+        buffer.undo();
+        assert_eq!(buffer.highlight_state(2, later), outside);
+        buffer.insert_text("x\ny\n", 0);
+        assert_ne!(buffer.highlight_state(3, later), outside);
+        assert_eq!(buffer.highlight_state(4, later), outside);
+        buffer.language_override = Some(Language::PlainText);
+        assert_eq!(buffer.highlight_state(4, later), None);
+    }
+
+    #[test]
+    fn dirty_state_follows_history() {
+        let mut buffer = FileBuffer::new("t", "abc".into(), None);
+        assert!(!buffer.is_dirty());
+        type_text(&mut buffer, "d");
+        assert!(buffer.is_dirty());
+        buffer.undo();
+        assert!(!buffer.is_dirty());
+        buffer.redo();
+        buffer.mark_clean();
+        assert!(!buffer.is_dirty());
+        type_text(&mut buffer, "e");
+        assert!(buffer.is_dirty());
+        buffer.undo();
+        assert!(!buffer.is_dirty());
+        buffer.undo();
+        assert!(buffer.is_dirty());
+        buffer.replace_all(&literal("abc"), "xyz", None);
+        assert!(buffer.is_dirty());
+    }
+
+    #[test]
+    fn set_content_is_one_step() {
+        let mut buffer = FileBuffer::new("t", "on disk".into(), None);
+        buffer.set_content("recovered");
+        assert!(buffer.is_dirty());
+        buffer.undo();
+        assert_eq!(buffer.text().to_string(), "on disk");
+        assert!(!buffer.is_dirty());
+    }
+
+    #[test]
+    fn open_and_save_errors_are_classified() {
+        let dir = std::env::temp_dir().join(format!("notepad2-errors-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let missing = FileBuffer::open(&dir.join("missing.txt"));
+        assert!(matches!(missing, Err(BufferError::NotFound(_, None))));
+
+        let binary = dir.join("binary.bin");
+        fs::write(&binary, [b'a', 0x00, 0x00, 0xff, 0x01]).unwrap();
+        assert!(matches!(FileBuffer::open(&binary), Err(BufferError::InvalidText(_, _))));
+
+        let mut untitled = FileBuffer::new("Untitled", "text".into(), None);
+        assert!(matches!(untitled.save(BackupMode::None), Err(BufferError::NoPath)));
+
+        // A failed save says which step of the atomic write went wrong.
+        let mut orphan = FileBuffer::new("orphan", "text".into(), Some(dir.join("gone").join("orphan.txt").display().to_string()));
+        let err = orphan.save(BackupMode::None).unwrap_err();
+        assert!(matches!(err, BufferError::NotFound(_, Some(_))));
+        assert!(err.to_string().contains("could not create temporary file"), "{}", err);
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn saves_back_in_the_encoding_it_was_opened_with() {
+        let path = std::env::temp_dir().join(format!("notepad2-latin1-{}", std::process::id()));
+        fs::write(&path, b"caf\xe9").unwrap();
+
+        let mut buffer = FileBuffer::open(&path).unwrap();
+        assert_eq!(buffer.text().to_string(), "caf\u{e9}");
+        assert_eq!(buffer.encoding.encoding, encoding_rs::WINDOWS_1252);
+        buffer.insert_text("!", 4);
+        buffer.save(BackupMode::None).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"caf\xe9!");
+
+        buffer.insert_text("\u{65e5}", 5);
+        assert!(matches!(buffer.save(BackupMode::None), Err(BufferError::Unencodable(_, _))));
+        assert_eq!(fs::read(&path).unwrap(), b"caf\xe9!");
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn reopening_in_another_encoding_keeps_the_tab_view() {
+        let path = std::env::temp_dir().join(format!("notepad2-reopen-{}", std::process::id()));
+        fs::write(&path, b"caf\xc3\xa9 au lait").unwrap();
+
+        let mut buffer = FileBuffer::open(&path).unwrap();
+        buffer.name = "renamed".into();
+        buffer.language_override = Some(Language::Markdown);
+        buffer.cursor = 12;
+        buffer.selection = 9..12;
+        buffer.scroll = Vec2::new(0.0, 40.0);
+        let id = buffer.id;
+        buffer.reopen_with(TextEncoding { encoding: encoding_rs::WINDOWS_1252, bom: false }).unwrap();
+        assert_eq!(buffer.text().to_string(), "caf\u{c3}\u{a9} au lait");
+        assert_eq!((buffer.id, buffer.name.as_str(), buffer.language()), (id, "renamed", Language::Markdown));
+        assert_eq!((buffer.cursor, buffer.selection.clone()), (12, 9..12));
+        assert_eq!(buffer.pending_scroll, Some(Vec2::new(0.0, 40.0)));
+
+        // Back as UTF-8 the text is a character shorter, and the cursor stays inside it.
+        buffer.cursor = 13;
+        buffer.selection = 11..13;
+        buffer.reopen_with(TextEncoding::UTF8).unwrap();
+        assert_eq!((buffer.cursor, buffer.selection.clone()), (12, 11..12));
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn keeps_crlf_and_converts_on_request() {
+        let path = std::env::temp_dir().join(format!("notepad2-crlf-{}", std::process::id()));
+        fs::write(&path, "one\r\ntwo\r\n").unwrap();
+
+        let mut buffer = FileBuffer::open(&path).unwrap();
+        assert_eq!(buffer.text().to_string(), "one\ntwo\n");
+        assert_eq!(buffer.line_endings.style, LineEnding::CrLf);
+        // Pasted CRLF text is stored with plain newlines too.
+        assert_eq!(buffer.insert_text("three\r\n", 8), 6);
+        buffer.save(BackupMode::None).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\r\ntwo\r\nthree\r\n");
+
+        buffer.set_line_ending(LineEnding::Lf);
+        assert!(buffer.is_dirty());
+        buffer.save(BackupMode::None).unwrap();
+        assert!(!buffer.is_dirty());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\ntwo\nthree\n");
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn replacing_in_a_file_keeps_each_line_ending() {
+        let path = std::env::temp_dir().join(format!("notepad2-replace-mixed-{}", std::process::id()));
+        fs::write(&path, b"caf\xe9 old\r\nold\nkeep\rold").unwrap();
+
+        let matcher = Matcher::new("old", SearchOptions::default()).unwrap();
+        assert_eq!(FileBuffer::replace_in_file(&path, &matcher, "new", BackupMode::None).unwrap(), 3);
+        assert_eq!(fs::read(&path).unwrap(), b"caf\xe9 new\r\nnew\nkeep\rnew");
+
+        // A pattern taking the line break along replaces it too.
+        let matcher = Matcher::new(r"new\s", SearchOptions { regex: true, ..Default::default() }).unwrap();
+        assert_eq!(FileBuffer::replace_in_file(&path, &matcher, "", BackupMode::None).unwrap(), 2);
+        assert_eq!(fs::read(&path).unwrap(), b"caf\xe9 keep\rnew");
+        let _ = fs::remove_file(path);
+    }
+}
@@ -0,0 +1,236 @@
+//! Find in Files: walks a folder on a worker thread and streams back the
+//! files that match, so the UI keeps drawing while a large tree is searched.
+//! The walk skips what git would ignore, using the `ignore` crate's reading
+//! of nested `.gitignore` files, `.git/info/exclude` and the global excludes
+//! file, whether or not the folder is in a repository.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+
+use eframe::egui::Context;
+use ignore::WalkBuilder;
+
+use crate::file_buffer::FileBuffer;
+use crate::results::{self, ResultGroup, ResultSource, SearchResults};
+use crate::search::Matcher;
+
+/// Files larger than this are skipped rather than read into memory.
+const MAX_FILE_LEN: u64 = 16 * 1024 * 1024;
+
+enum Event {
+    Found(ResultGroup),
+    Searched,
+    /// A binary, oversized or unreadable file.
+    Skipped,
+    Done,
+}
+
+/// A search running in the background. Dropping it stops the worker.
+pub struct FileSearch {
+    receiver: Receiver<Event>,
+    cancel: Arc<AtomicBool>,
+    pub running: bool,
+    pub files_searched: usize,
+    pub files_skipped: usize,
+}
+
+impl FileSearch {
+    /// Starts searching `root`. `ctx` is repainted whenever results arrive.
+    pub fn start(root: PathBuf, matcher: Matcher, ctx: Context) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let worker = Worker { matcher, sender, cancel: cancel.clone(), ctx };
+        thread::spawn(move || {
+            worker.walk(&root);
+            worker.send(Event::Done);
+        });
+        Self { receiver, cancel, running: true, files_searched: 0, files_skipped: 0 }
+    }
+
+    pub fn cancel(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+        self.running = false;
+    }
+
+    /// Moves whatever the worker has found so far into `results`.
+    pub fn poll(&mut self, results: &mut SearchResults) {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(Event::Found(group)) => {
+                    self.files_searched += 1;
+                    results.groups.push(group);
+                }
+                Ok(Event::Searched) => self.files_searched += 1,
+                Ok(Event::Skipped) => self.files_skipped += 1,
+                Ok(Event::Done) | Err(TryRecvError::Disconnected) => {
+                    self.running = false;
+                    return;
+                }
+                Err(TryRecvError::Empty) => return,
+            }
+        }
+    }
+}
+
+impl Drop for FileSearch {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+struct Worker {
+    matcher: Matcher,
+    sender: Sender<Event>,
+    cancel: Arc<AtomicBool>,
+    ctx: Context,
+}
+
+impl Worker {
+    fn send(&self, event: Event) {
+        if self.sender.send(event).is_ok() {
+            self.ctx.request_repaint();
+        }
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    /// Searches `root` depth first in name order. Hidden files are searched
+    /// like any other, but not `.git`. Symlinks are not followed, so a link
+    /// back up the tree cannot loop forever.
+    fn walk(&self, root: &Path) {
+        let walk = WalkBuilder::new(root)
+            .hidden(false)
+            .ignore(false)
+            .parents(true)
+            .require_git(false)
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .filter_entry(|entry| entry.file_name() != ".git")
+            .build();
+        for entry in walk {
+            if self.cancelled() {
+                break;
+            }
+            let Ok(entry) = entry else {
+                self.send(Event::Skipped);
+                continue;
+            };
+            if entry.file_type().is_some_and(|kind| kind.is_file()) {
+                let len = entry.metadata().map_or(0, |m| m.len());
+                self.search_file(entry.into_path(), len);
+            }
+        }
+    }
+
+    fn search_file(&self, path: PathBuf, len: u64) {
+        if len > MAX_FILE_LEN {
+            self.send(Event::Skipped);
+            return;
+        }
+        // Opening detects the encoding and turns binary files away.
+        let Ok(buffer) = FileBuffer::open(&path) else {
+            self.send(Event::Skipped);
+            return;
+        };
+        let found = results::search_buffer(&self.matcher, &buffer, None);
+        if found.is_empty() {
+            self.send(Event::Searched);
+        } else {
+            let name = path.display().to_string();
+            self.send(Event::Found(ResultGroup { name, source: ResultSource::File(path), results: found }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::SearchOptions;
+    use std::fs;
+    use std::time::{Duration, Instant};
+
+    /// Searches `root` for "needle" and waits for the search to finish.
+    fn finished_search(root: &Path) -> (FileSearch, SearchResults) {
+        let matcher = Matcher::new("needle", SearchOptions::default()).unwrap();
+        let mut results = SearchResults::new(String::new(), matcher.clone());
+        let mut search = FileSearch::start(root.to_path_buf(), matcher, Context::default());
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while search.running && Instant::now() < deadline {
+            search.poll(&mut results);
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(!search.running);
+        (search, results)
+    }
+
+    /// Every file under `root` a search for "needle" finds, relative to `root`.
+    fn found_files(root: &Path) -> Vec<String> {
+        let (_, results) = finished_search(root);
+        results
+            .groups
+            .iter()
+            .filter_map(|group| match &group.source {
+                ResultSource::File(path) => Some(path.strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/")),
+                ResultSource::Tab(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn skips_ignored_and_binary_files() {
+        let root = std::env::temp_dir().join(format!("notepad2-find-in-files-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("target")).unwrap();
+        fs::write(root.join(".gitignore"), "target/\n*.log\n").unwrap();
+        fs::write(root.join("src/main.rs"), "fn needle() {}\n// needle\n").unwrap();
+        fs::write(root.join("src/other.rs"), "nothing").unwrap();
+        fs::write(root.join("target/out.rs"), "needle").unwrap();
+        fs::write(root.join("debug.log"), "needle").unwrap();
+        fs::write(root.join("data.bin"), b"needle\0\x01\x02").unwrap();
+
+        let (search, results) = finished_search(&root);
+        let found: Vec<_> = results.groups.iter().map(|g| g.source.clone()).collect();
+        assert!(found == vec![ResultSource::File(root.join("src/main.rs"))]);
+        assert_eq!(results.groups[0].results.len(), 2);
+        assert_eq!((search.files_searched, search.files_skipped), (3, 1));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn honours_nested_gitignores_negation_double_stars_and_info_exclude() {
+        let root = std::env::temp_dir().join(format!("notepad2-find-gitignore-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let files = [
+            "a.log",
+            "keep.log",
+            "build/out.txt",
+            "src/build.txt",
+            "x/cache/y.txt",
+            "a/z",
+            "a/b/c/z",
+            "notes/b.txt",
+            "notes/c.md",
+            "secret.md",
+            ".hidden",
+            ".git/config",
+        ];
+        for file in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "needle").unwrap();
+        }
+        fs::write(root.join(".gitignore"), "*.log\n!keep.log\nbuild/\n**/cache/**\na/**/z\n*.txt\n").unwrap();
+        // An inner file overrides the outer one below it.
+        fs::write(root.join("notes/.gitignore"), "!*.txt\n*.md\n").unwrap();
+        fs::create_dir_all(root.join(".git/info")).unwrap();
+        fs::write(root.join(".git/info/exclude"), "secret.md\n").unwrap();
+
+        assert_eq!(found_files(&root), [".hidden", "keep.log", "notes/b.txt"]);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
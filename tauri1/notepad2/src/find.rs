@@ -0,0 +1,457 @@
+//! Stepping through and replacing the find bar's matches in the current tab,
+//! and searching across tabs and folders.
+
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use eframe::egui::Context;
+
+use crate::file_buffer::FileBuffer;
+use crate::file_search::FileSearch;
+use crate::notepad_app::NotepadApp;
+use crate::results::{ResultSource, SearchResults};
+use crate::search::Matcher;
+
+/// Whether the find bar is shown, and where keyboard focus should go next.
+/// The menu, the shortcuts and the bar itself all go through [`FindBar::open`]
+/// and [`FindBar::close`]; the two transient states hand focus over for one frame.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum FindBar {
+    #[default]
+    Closed,
+    /// Shown; the query field takes focus on the next frame.
+    Opening,
+    Open,
+    /// Hidden; the editor takes focus back on the next frame.
+    Closing,
+}
+
+impl FindBar {
+    pub fn is_open(self) -> bool {
+        matches!(self, FindBar::Opening | FindBar::Open)
+    }
+
+    /// Shows the bar, or moves focus back to its query field if already shown.
+    pub fn open(&mut self) {
+        *self = FindBar::Opening;
+    }
+
+    pub fn close(&mut self) {
+        if self.is_open() {
+            *self = FindBar::Closing;
+        }
+    }
+
+    /// True once after [`FindBar::open`], for the query field to request focus.
+    pub fn take_query_focus(&mut self) -> bool {
+        let take = *self == FindBar::Opening;
+        if take {
+            *self = FindBar::Open;
+        }
+        take
+    }
+
+    /// True once after [`FindBar::close`], for the editor to request focus.
+    pub fn take_editor_focus(&mut self) -> bool {
+        let take = *self == FindBar::Closing;
+        if take {
+            *self = FindBar::Closed;
+        }
+        take
+    }
+}
+
+/// The text "replace in selection" is limited to, as a character range. Only
+/// valid for the buffer version it was taken from; replacements move it along.
+#[derive(Clone)]
+pub struct FindScope {
+    pub buffer_id: u64,
+    pub version: u64,
+    pub chars: Range<usize>,
+}
+
+impl NotepadApp {
+    /// Re-runs the search when the tab or its text changed since the last run.
+    /// Clear `find_searched` to force a new search after the query changes.
+    pub fn refresh_find_matches(&mut self) {
+        let Some(buffer) = self.buffers.get(self.current_tab) else {
+            self.find_matches.clear();
+            return;
+        };
+        let key = (buffer.id, buffer.version());
+        if self.find_searched == Some(key) {
+            return;
+        }
+        self.find_searched = Some(key);
+        self.find_current = None;
+
+        // Any other edit, or switching tabs, leaves the scope meaningless.
+        if self.find_scope.as_ref().is_some_and(|s| (s.buffer_id, s.version) != key) {
+            self.find_scope = None;
+            self.status.set("Selection changed; searching the whole text".to_string());
+        }
+        let bounds = self
+            .find_scope
+            .as_ref()
+            .map(|s| buffer.char_to_byte(s.chars.start)..buffer.char_to_byte(s.chars.end));
+        self.find_matches = match Matcher::new(&self.find_query, self.find_options) {
+            Ok(matcher) => buffer
+                .find_all(&matcher)
+                .into_iter()
+                .filter(|m| bounds.as_ref().is_none_or(|b| b.start <= m.start && m.end <= b.end))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+    }
+
+    /// Limits find and replace to the current tab's selection, or lifts the limit.
+    pub fn set_find_in_selection(&mut self, enabled: bool) {
+        self.find_scope = None;
+        self.find_searched = None;
+        if !enabled {
+            return;
+        }
+        let Some(buffer) = self.buffers.get(self.current_tab) else {
+            return;
+        };
+        if buffer.selection.is_empty() {
+            self.status.set("Select some text to replace in first".to_string());
+            return;
+        }
+        self.find_scope = Some(FindScope {
+            buffer_id: buffer.id,
+            version: buffer.version(),
+            chars: buffer.selection.clone(),
+        });
+    }
+
+    /// The match the editor has selected, if the cursor is still at its end.
+    fn selected_match(&self) -> Option<usize> {
+        let buffer = self.buffers.get(self.current_tab)?;
+        self.find_current
+            .filter(|&i| buffer.cursor == buffer.byte_to_char(self.find_matches[i].end))
+    }
+
+    /// Selects the next match after the cursor, or the previous one before it,
+    /// wrapping around the ends of the text.
+    pub fn find_step(&mut self, forward: bool) {
+        self.refresh_find_matches();
+        let current = self.selected_match();
+        let Some(buffer) = self.buffers.get_mut(self.current_tab) else {
+            return;
+        };
+        let count = self.find_matches.len();
+        if count == 0 {
+            return;
+        }
+
+        let cursor = buffer.char_to_byte(buffer.cursor);
+        let next = match (current, forward) {
+            (Some(i), true) => Some(i + 1).filter(|&i| i < count),
+            (Some(i), false) => i.checked_sub(1),
+            (None, true) => self.find_matches.iter().position(|m| m.start >= cursor),
+            (None, false) => self.find_matches.iter().rposition(|m| m.start < cursor),
+        };
+        let index = match next {
+            Some(index) => index,
+            None if forward => {
+                self.status.set("Search wrapped to the top".to_string());
+                0
+            }
+            None => {
+                self.status.set("Search wrapped to the bottom".to_string());
+                count - 1
+            }
+        };
+
+        let range = &self.find_matches[index];
+        let selection = buffer.byte_to_char(range.start)..buffer.byte_to_char(range.end);
+        buffer.cursor = selection.end;
+        buffer.pending_selection = Some(selection);
+        self.find_current = Some(index);
+    }
+
+    /// Replaces the selected match and moves on to the next one. With no match
+    /// selected, this only selects the next one, so the user sees what changes.
+    pub fn replace_current(&mut self) {
+        self.refresh_find_matches();
+        let Some(index) = self.selected_match() else {
+            self.find_step(true);
+            return;
+        };
+        let matcher = match Matcher::new(&self.find_query, self.find_options) {
+            Ok(matcher) => matcher,
+            Err(err) => {
+                self.find_error = Some(err);
+                return;
+            }
+        };
+        let Some(buffer) = self.buffers.get_mut(self.current_tab) else {
+            return;
+        };
+        let range = self.find_matches[index].clone();
+        let end = buffer.byte_to_char(range.end);
+        let len_before = buffer.len_chars();
+        if !buffer.replace_match(&matcher, range, &self.replace_query) {
+            return;
+        }
+        let delta = buffer.len_chars() as isize - len_before as isize;
+        // Continue after the replacement, so text it inserted is not matched again.
+        buffer.cursor = (end as isize + delta) as usize;
+        self.shift_find_scope(delta);
+        self.status.set("Replaced 1 occurrence".to_string());
+        self.find_step(true);
+    }
+
+    /// Replaces every match, or every match in the scope, as one undo step.
+    pub fn replace_all_matches(&mut self) {
+        let matcher = match Matcher::new(&self.find_query, self.find_options) {
+            Ok(matcher) => matcher,
+            Err(err) => {
+                self.find_error = Some(err);
+                return;
+            }
+        };
+        let within = self.find_scope.as_ref().map(|s| s.chars.clone());
+        let Some(buffer) = self.buffers.get_mut(self.current_tab) else {
+            return;
+        };
+        let len_before = buffer.len_chars();
+        let count = buffer.replace_all(&matcher, &self.replace_query, within);
+        let delta = buffer.len_chars() as isize - len_before as isize;
+        self.shift_find_scope(delta);
+        let message = match (count, self.find_scope.is_some()) {
+            (0, _) => "No matches to replace".to_string(),
+            (count, true) => format!("Replaced {} occurrence(s) in selection", count),
+            (count, false) => format!("Replaced {} occurrence(s)", count),
+        };
+        self.status.set(message);
+    }
+
+    /// Keeps the scope in step with a replacement inside it.
+    fn shift_find_scope(&mut self, delta: isize) {
+        let Some(buffer) = self.buffers.get(self.current_tab) else {
+            return;
+        };
+        if let Some(scope) = &mut self.find_scope {
+            scope.chars.end = (scope.chars.end as isize + delta).max(scope.chars.start as isize) as usize;
+            scope.version = buffer.version();
+        }
+    }
+
+    /// Lists the query's matches in every open tab in the results panel.
+    pub fn find_in_open_tabs(&mut self) {
+        let matcher = match Matcher::new(&self.find_query, self.find_options) {
+            Ok(matcher) => matcher,
+            Err(err) => {
+                self.find_error = Some(err);
+                return;
+            }
+        };
+        let title = format!("\"{}\" in open tabs", self.find_query);
+        let results = SearchResults::from_buffers(title, &matcher, &self.buffers);
+        self.file_search = None;
+        self.status.set(format!("{} match(es) in {} tab(s)", results.len(), results.groups.len()));
+        self.search_results = Some(results);
+    }
+
+    /// Starts searching the files under `root` in the background; results
+    /// stream into the results panel.
+    pub fn find_in_files(&mut self, root: PathBuf, ctx: &Context) {
+        let matcher = match Matcher::new(&self.find_query, self.find_options) {
+            Ok(matcher) => matcher,
+            Err(err) => {
+                self.find_error = Some(err);
+                return;
+            }
+        };
+        if !root.is_dir() {
+            self.report_error(format!("{}: not a folder", root.display()));
+            return;
+        }
+        let title = format!("\"{}\" in {}", self.find_query, root.display());
+        self.search_results = Some(SearchResults::new(title, matcher.clone()));
+        self.file_search = Some(FileSearch::start(root, matcher, ctx.clone()));
+    }
+
+    /// Collects results from a running Find in Files; called every frame.
+    pub fn poll_file_search(&mut self) {
+        let (Some(search), Some(results)) = (&mut self.file_search, &mut self.search_results) else {
+            return;
+        };
+        if !search.running {
+            return;
+        }
+        search.poll(results);
+        if !search.running {
+            let message = format!(
+                "{} match(es) in {} of {} file(s)",
+                results.len(),
+                results.groups.len(),
+                search.files_searched
+            );
+            self.status.set(message);
+        }
+    }
+
+    /// The tab showing `path`, if it is open.
+    pub fn tab_for_path(&self, path: &Path) -> Option<usize> {
+        self.buffers.iter().position(|b| b.file_path.as_deref().is_some_and(|p| Path::new(p) == path))
+    }
+
+    /// Switches to the tab a result came from, opening its file if needed, and
+    /// selects the match. If the text changed since the search, goes to the
+    /// start of the line instead.
+    pub fn open_search_result(&mut self, group: usize, index: usize) {
+        let Some(group) = self.search_results.as_ref().and_then(|r| r.groups.get(group)) else {
+            return;
+        };
+        let Some(result) = group.results.get(index) else {
+            return;
+        };
+        let (source, line, chars, version) = (group.source.clone(), result.line, result.chars.clone(), result.version);
+        let tab = match source {
+            ResultSource::Tab(id) => self.buffers.iter().position(|b| b.id == id),
+            ResultSource::File(path) => match self.tab_for_path(&path) {
+                Some(tab) => Some(tab),
+                None => match FileBuffer::open(&path) {
+                    Ok(buffer) => {
+                        self.buffers.push(buffer);
+                        Some(self.buffers.len() - 1)
+                    }
+                    Err(err) => {
+                        self.report_error(format!("Could not open {}", err));
+                        return;
+                    }
+                },
+            },
+        };
+        let Some(tab) = tab else {
+            self.status.set("That tab has been closed".to_string());
+            return;
+        };
+        self.current_tab = tab;
+        let buffer = &mut self.buffers[tab];
+        let unchanged = match version {
+            Some(version) => buffer.version() == version,
+            None => !buffer.is_dirty(),
+        };
+        if unchanged {
+            buffer.cursor = chars.end;
+            buffer.pending_selection = Some(chars);
+        } else {
+            let start = buffer.line_to_char(line);
+            buffer.cursor = start;
+            buffer.pending_selection = Some(start..start);
+        }
+    }
+
+    /// Shows what Replace in Files would change, line by line.
+    pub fn preview_replace_in_files(&mut self) {
+        if let Some(results) = &mut self.search_results {
+            results.preview_replace(&self.replace_query);
+        }
+    }
+
+    /// Replaces every match of the search that produced the results in the
+    /// files it listed. Files open in a tab are changed there, as one undo
+    /// step, and left unsaved; the rest are rewritten on disk only where they
+    /// matched, keeping their encoding and each line's own line ending.
+    pub fn replace_in_files(&mut self) {
+        let Some(results) = self.search_results.take() else {
+            return;
+        };
+        let matcher = results.matcher;
+        self.file_search = None;
+        let (mut replaced, mut files, mut in_tabs, mut failed) = (0, 0, 0, 0);
+        for group in results.groups {
+            let ResultSource::File(path) = group.source else {
+                continue;
+            };
+            let count = match self.tab_for_path(&path) {
+                Some(tab) => {
+                    let count = self.buffers[tab].replace_all(&matcher, &self.replace_query, None);
+                    if count > 0 {
+                        in_tabs += 1;
+                    }
+                    count
+                }
+                None => match FileBuffer::replace_in_file(&path, &matcher, &self.replace_query, self.backup) {
+                    Ok(count) => count,
+                    Err(err) => {
+                        failed += 1;
+                        self.report_error(format!("Could not replace in {}", err));
+                        continue;
+                    }
+                },
+            };
+            replaced += count;
+            if count > 0 {
+                files += 1;
+            }
+        }
+        self.find_searched = None;
+        let mut status = format!("Replaced {} occurrence(s) in {} file(s)", replaced, files);
+        if in_tabs > 0 {
+            status.push_str(&format!("; {} open tab(s) changed but not saved", in_tabs));
+        }
+        if failed > 0 {
+            status.push_str(&format!("; {} file(s) failed, see the error log", failed));
+        }
+        self.status.set(status);
+    }
+
+    /// "3 of 17", or a count when no match is selected.
+    pub fn find_counter(&self) -> String {
+        match (self.find_current, self.find_matches.len()) {
+            (_, 0) => "No results".to_string(),
+            (Some(i), count) => format!("{} of {}", i + 1, count),
+            (None, 1) => "1 match".to_string(),
+            (None, count) => format!("{} matches", count),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opening_focuses_the_query_once() {
+        let mut bar = FindBar::default();
+        assert!(!bar.is_open());
+        assert!(!bar.take_query_focus());
+
+        bar.open();
+        assert!(bar.is_open());
+        assert!(!bar.take_editor_focus());
+        assert!(bar.take_query_focus());
+        assert!(!bar.take_query_focus());
+        assert_eq!(bar, FindBar::Open);
+
+        // Ctrl+F while open pulls focus back into the field.
+        bar.open();
+        assert!(bar.take_query_focus());
+    }
+
+    #[test]
+    fn closing_returns_focus_to_the_editor_once() {
+        let mut bar = FindBar::default();
+        // Escape with the bar already hidden leaves focus alone.
+        bar.close();
+        assert!(!bar.take_editor_focus());
+
+        bar.open();
+        bar.close();
+        assert!(!bar.is_open());
+        assert!(!bar.take_query_focus());
+        assert!(bar.take_editor_focus());
+        assert!(!bar.take_editor_focus());
+        assert_eq!(bar, FindBar::Closed);
+
+        // Closed before the field ever got focus.
+        bar.open();
+        bar.close();
+        assert_eq!(bar, FindBar::Closing);
+    }
+}
@@ -0,0 +1,101 @@
+//! The editor font: one of egui's built-in families, or a TTF/OTF file the
+//! user picked, plus fallback fonts for scripts it lacks, such as CJK.
+//!
+//! egui lays text out glyph by glyph without shaping, so a font's ligatures
+//! are not drawn; its ordinary glyphs are.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use eframe::egui::{FontData, FontDefinitions, FontFamily};
+
+/// Family name the user's editor font is registered under.
+pub const EDITOR_FAMILY: &str = "editor";
+pub const DEFAULT_FONT_SIZE: f32 = 14.0;
+pub const MIN_FONT_SIZE: f32 = 6.0;
+pub const MAX_FONT_SIZE: f32 = 72.0;
+
+/// Font files to load on top of egui's own.
+#[derive(Clone, PartialEq, Default)]
+pub struct FontFiles {
+    /// Used for the editor text instead of a built-in family.
+    pub editor: Option<PathBuf>,
+    /// Tried in order for characters the other fonts do not have.
+    pub fallbacks: Vec<PathBuf>,
+}
+
+/// egui's default fonts plus `files`. Fallbacks are added to the built-in
+/// families as well, so the rest of the UI can show those scripts too. A file
+/// that cannot be loaded is left out, with a message saying why.
+pub fn font_definitions(files: &FontFiles) -> (FontDefinitions, Vec<String>) {
+    let mut defs = FontDefinitions::default();
+    let mut errors = Vec::new();
+    let mut load = |defs: &mut FontDefinitions, path: &Path| match read_font(path) {
+        Ok(data) => {
+            let name = path.display().to_string();
+            defs.font_data.insert(name.clone(), Arc::new(FontData::from_owned(data)));
+            Some(name)
+        }
+        Err(err) => {
+            errors.push(err);
+            None
+        }
+    };
+
+    let editor = files.editor.as_deref().and_then(|path| load(&mut defs, path));
+    let fallbacks: Vec<String> = files.fallbacks.iter().filter_map(|path| load(&mut defs, path)).collect();
+    for family in [FontFamily::Monospace, FontFamily::Proportional] {
+        defs.families.entry(family).or_default().extend(fallbacks.iter().cloned());
+    }
+    if let Some(editor) = editor {
+        // Glyphs the chosen font lacks come from the fallbacks, then the built-in monospace fonts.
+        let mut family = vec![editor];
+        family.extend(defs.families[&FontFamily::Monospace].iter().cloned());
+        defs.families.insert(FontFamily::Name(EDITOR_FAMILY.into()), family);
+    }
+    (defs, errors)
+}
+
+/// Reads a font file, checking it parses so egui does not panic on it later.
+fn read_font(path: &Path) -> Result<Vec<u8>, String> {
+    let data = fs::read(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    ab_glyph::FontRef::try_from_slice(&data)
+        .map_err(|_| format!("{}: not a TrueType or OpenType font", path.display()))?;
+    Ok(data)
+}
+
+/// Clamps an editor font size to the supported range, in half points.
+pub fn clamp_font_size(size: f32) -> f32 {
+    ((size * 2.0).round() / 2.0).clamp(MIN_FONT_SIZE, MAX_FONT_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unloadable_files_are_reported_and_left_out() {
+        let dir = std::env::temp_dir().join(format!("notepad2-fonts-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let fake = dir.join("fake.ttf");
+        fs::write(&fake, "not really a font").unwrap();
+
+        let files = FontFiles { editor: Some(fake), fallbacks: vec![dir.join("missing.otf")] };
+        let (defs, errors) = font_definitions(&files);
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].ends_with("fake.ttf: not a TrueType or OpenType font"));
+        assert!(errors[1].contains("missing.otf"));
+        assert!(!defs.families.contains_key(&FontFamily::Name(EDITOR_FAMILY.into())));
+        assert!(defs.families == FontDefinitions::default().families);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn font_sizes_snap_to_half_points_within_range() {
+        assert_eq!(clamp_font_size(13.3), 13.5);
+        assert_eq!(clamp_font_size(2.0), MIN_FONT_SIZE);
+        assert_eq!(clamp_font_size(500.0), MAX_FONT_SIZE);
+    }
+}
@@ -0,0 +1,88 @@
+//! Parsing for the Go to Line dialog: `line`, `line:column`, or a line offset
+//! from the cursor such as `+20` or `-5`, optionally with `:column`.
+
+use crate::file_buffer::FileBuffer;
+
+/// State of the open Go to Line dialog.
+#[derive(Default)]
+pub struct GotoDialog {
+    pub input: String,
+    pub error: Option<String>,
+    /// Whether the input field has been focused since the dialog opened.
+    pub focused: bool,
+}
+
+/// The character index `input` points at in `buffer`, or why it does not
+/// point anywhere. Lines and columns are one-based, as the status bar shows them.
+pub fn resolve(input: &str, buffer: &FileBuffer) -> Result<usize, String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err("Enter a line number".to_string());
+    }
+    let (line_part, column_part) = match input.split_once(':') {
+        Some((line, column)) => (line.trim(), Some(column.trim())),
+        None => (input, None),
+    };
+
+    let lines = buffer.len_lines();
+    let current = buffer.char_to_line(buffer.cursor) as i64 + 1;
+    let out_of_range = || format!("Line must be between 1 and {}", lines);
+    let line = match line_part.chars().next() {
+        Some('+') | Some('-') => current.checked_add(parse_number(line_part)?).ok_or_else(out_of_range)?,
+        _ => parse_number(line_part)?,
+    };
+    if line < 1 || line > lines as i64 {
+        return Err(out_of_range());
+    }
+    let line = line as usize - 1;
+
+    let start = buffer.line_to_char(line);
+    let Some(column_part) = column_part else {
+        return Ok(start);
+    };
+    let end = if line + 1 < lines { buffer.line_to_char(line + 1) - 1 } else { buffer.len_chars() };
+    let columns = end - start + 1;
+    match parse_number(column_part)? {
+        column if column >= 1 && column <= columns as i64 => Ok(start + column as usize - 1),
+        _ => Err(format!("Column must be between 1 and {} on line {}", columns, line + 1)),
+    }
+}
+
+fn parse_number(text: &str) -> Result<i64, String> {
+    text.parse().map_err(|_| format!("\"{}\" is not a number", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn absolute_relative_and_column_targets() {
+        let mut buffer = FileBuffer::new("t", "one\ntwo\nthree\n".to_string(), None);
+        assert_eq!(resolve("2", &buffer), Ok(4));
+        assert_eq!(resolve(" 3:4 ", &buffer), Ok(11));
+        // The empty line after the final newline is line 4.
+        assert_eq!(resolve("4", &buffer), Ok(14));
+        // One past the last character sits at the end of the line.
+        assert_eq!(resolve("1:4", &buffer), Ok(3));
+
+        buffer.cursor = 5;
+        assert_eq!(resolve("+1", &buffer), Ok(8));
+        assert_eq!(resolve("-1:2", &buffer), Ok(1));
+        // Offsets too large to add to the current line are out of range, not an overflow.
+        let out_of_range = Err("Line must be between 1 and 4".to_string());
+        assert_eq!(resolve("+9223372036854775807", &buffer), out_of_range);
+        assert_eq!(resolve("-9223372036854775808", &buffer), out_of_range);
+    }
+
+    #[test]
+    fn rejects_out_of_range_and_malformed_input() {
+        let buffer = FileBuffer::new("t", "one\ntwo".to_string(), None);
+        assert_eq!(resolve("3", &buffer), Err("Line must be between 1 and 2".to_string()));
+        assert_eq!(resolve("0", &buffer), Err("Line must be between 1 and 2".to_string()));
+        assert_eq!(resolve("-1", &buffer), Err("Line must be between 1 and 2".to_string()));
+        assert_eq!(resolve("1:5", &buffer), Err("Column must be between 1 and 4 on line 1".to_string()));
+        assert!(resolve("two", &buffer).is_err());
+        assert!(resolve("", &buffer).is_err());
+    }
+}
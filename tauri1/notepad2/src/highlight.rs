@@ -0,0 +1,556 @@
+//! Syntax highlighting for the editor, parsed by syntect's Sublime Text
+//! grammars. The parser state at the start of each line is kept in the
+//! buffer's [`Highlighter`], which the buffer's edits invalidate from the line
+//! they touch. Only the lines the editor lays out are parsed for their spans,
+//! and [`SpanCache`] keeps those from being parsed again every frame.
+//!
+//! Rust, Python, JavaScript, C, JSON, Markdown and shell use the grammars
+//! bundled with syntect; TOML uses `syntaxes/TOML.sublime-syntax`. The scopes
+//! a grammar reports are folded into the few [`TokenKind`]s a theme colors,
+//! which leaves these constructs unsupported:
+//!
+//! - Function and variable names, operators and punctuation are not colored.
+//! - Rust types are only colored where the grammar knows a type is expected,
+//!   such as after `struct` or in a generic bound, and primitive types are
+//!   colored as keywords, like `let`.
+//! - Python docstrings are colored as comments, not strings.
+//! - C files use the C++ grammar, and TypeScript and JSX use the JavaScript
+//!   one, without their own syntax.
+//! - Inside Markdown code fences everything is a string, whatever the fence's
+//!   language. Emphasis, HTML and tables are not colored.
+//! - TOML is colored without checking that keys, tables and dates are valid.
+//! - Lines longer than [`MAX_LINE_BYTES`] are not colored, and do not change
+//!   the state they pass on, so a string or comment opened on one is missed.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+
+use ropey::Rope;
+use syntect::easy::ScopeRangeIterator;
+use syntect::parsing::{ParseState, Scope, ScopeStack, ScopeStackOp, SyntaxDefinition, SyntaxSet};
+
+use crate::language::Language;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TokenKind {
+    Keyword,
+    Type,
+    Constant,
+    String,
+    Number,
+    Comment,
+    Heading,
+    Key,
+}
+
+/// Longer lines are left uncolored; grammars can take very long over them.
+pub const MAX_LINE_BYTES: usize = 10_000;
+
+/// The grammar for each language's files, by extension.
+fn extension(language: Language) -> Option<&'static str> {
+    match language {
+        Language::PlainText => None,
+        Language::Rust => Some("rs"),
+        Language::Python => Some("py"),
+        Language::JavaScript => Some("js"),
+        Language::C => Some("cpp"),
+        Language::Json => Some("json"),
+        Language::Toml => Some("toml"),
+        Language::Markdown => Some("md"),
+        Language::Shell => Some("sh"),
+    }
+}
+
+/// syntect's bundled grammars and ours, loaded the first time a file needs them.
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(|| {
+        let mut builder = SyntaxSet::load_defaults_newlines().into_builder();
+        let toml = SyntaxDefinition::load_from_str(include_str!("syntaxes/TOML.sublime-syntax"), true, None);
+        builder.add(toml.expect("the TOML grammar is valid"));
+        builder.build()
+    })
+}
+
+/// Which token kind a scope makes its text, tried in order against every scope
+/// the text is in. `None` leaves it uncolored.
+const SCOPE_KINDS: &[(&str, Option<TokenKind>)] = &[
+    ("comment", Some(TokenKind::Comment)),
+    ("markup.quote", Some(TokenKind::Comment)),
+    ("markup.heading", Some(TokenKind::Heading)),
+    ("entity.name.section", Some(TokenKind::Heading)),
+    ("markup.raw", Some(TokenKind::String)),
+    // Shell variables, also inside strings.
+    ("meta.group.expansion.parameter", Some(TokenKind::Key)),
+    ("variable.other.readwrite.shell", Some(TokenKind::Key)),
+    ("meta.structure.dictionary.key", Some(TokenKind::Key)),
+    ("meta.mapping.key", Some(TokenKind::Key)),
+    ("string", Some(TokenKind::String)),
+    ("constant.character", Some(TokenKind::String)),
+    ("constant.numeric", Some(TokenKind::Number)),
+    ("constant.language", Some(TokenKind::Constant)),
+    ("support.constant", Some(TokenKind::Constant)),
+    ("variable.language", Some(TokenKind::Keyword)),
+    ("markup.list.unnumbered.bullet", Some(TokenKind::Keyword)),
+    ("markup.list.numbered.bullet", Some(TokenKind::Keyword)),
+    ("markup.underline.link", Some(TokenKind::Type)),
+    ("storage.modifier.lifetime", Some(TokenKind::Type)),
+    ("entity.name.type", Some(TokenKind::Type)),
+    ("entity.name.struct", Some(TokenKind::Type)),
+    ("entity.name.enum", Some(TokenKind::Type)),
+    ("entity.name.trait", Some(TokenKind::Type)),
+    ("entity.name.class", Some(TokenKind::Type)),
+    ("entity.other.inherited-class", Some(TokenKind::Type)),
+    ("support.type", Some(TokenKind::Type)),
+    ("support.class", Some(TokenKind::Type)),
+    // Symbols like `=` and `&&`; operators spelled as words are keywords below.
+    ("keyword.operator", None),
+    ("keyword", Some(TokenKind::Keyword)),
+    ("storage", Some(TokenKind::Keyword)),
+];
+
+fn scope_kinds() -> &'static [(Scope, Option<TokenKind>)] {
+    static SCOPES: OnceLock<Vec<(Scope, Option<TokenKind>)>> = OnceLock::new();
+    SCOPES.get_or_init(|| {
+        SCOPE_KINDS.iter().map(|&(scope, kind)| (Scope::new(scope).expect("scope names are valid"), kind)).collect()
+    })
+}
+
+/// The kind of `text` found inside `scopes`, outermost first.
+fn kind(scopes: &[Scope], text: &str) -> Option<TokenKind> {
+    let (_, kind) = scope_kinds().iter().find(|(rule, _)| scopes.iter().any(|&scope| rule.is_prefix_of(scope)))?;
+    match kind {
+        // Operators spelled as words, like `as` and `and`.
+        None if text.starts_with(char::is_alphabetic) => Some(TokenKind::Keyword),
+        kind => *kind,
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Parser {
+    parse: ParseState,
+    /// The scopes open at this point, which the next line's spans are in.
+    scopes: ScopeStack,
+}
+
+/// Where parsing picks up at the start of a line. Cheap to clone; lines that
+/// start in the same state share it.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LineState(Arc<Parser>);
+
+impl LineState {
+    /// The state at the start of a file in `language`, or `None` for plain text.
+    pub fn start(language: Language) -> Option<Self> {
+        let syntax = syntaxes().find_syntax_by_extension(extension(language)?)?;
+        Some(LineState(Arc::new(Parser { parse: ParseState::new(syntax), scopes: ScopeStack::new() })))
+    }
+
+    /// Parses one `line`, which ends with its line break if it has one,
+    /// returning the scope changes in it and the state the next line starts in.
+    fn parse(&self, line: &str) -> (Vec<(usize, ScopeStackOp)>, LineState) {
+        if line.len() > MAX_LINE_BYTES {
+            return (Vec::new(), self.clone());
+        }
+        let mut parser = Parser::clone(&self.0);
+        let Ok(ops) = parser.parse.parse_line(line, syntaxes()) else {
+            return (Vec::new(), self.clone());
+        };
+        for (_, op) in &ops {
+            // A grammar popping more than it pushed only loses its scopes.
+            let _ = parser.scopes.apply(op);
+        }
+        let next = if parser == *self.0 { self.clone() } else { LineState(Arc::new(parser)) };
+        (ops, next)
+    }
+
+    /// Highlighted spans of one `line` that starts in this state, and the
+    /// state the next line starts in.
+    fn spans(&self, line: &str) -> (Vec<(Range<usize>, TokenKind)>, LineState) {
+        let (ops, next) = self.parse(line);
+        let mut spans: Vec<(Range<usize>, TokenKind)> = Vec::new();
+        let mut scopes = self.0.scopes.clone();
+        for (range, op) in ScopeRangeIterator::new(&ops, line) {
+            let _ = scopes.apply(op);
+            let text = &line[range.clone()];
+            let range = range.start..range.start + text.trim_end_matches(['\n', '\r']).len();
+            if range.is_empty() {
+                continue;
+            }
+            let Some(kind) = kind(scopes.as_slice(), text) else {
+                continue;
+            };
+            match spans.last_mut() {
+                Some((last, last_kind)) if last.end == range.start && *last_kind == kind => last.end = range.end,
+                _ => spans.push((range, kind)),
+            }
+        }
+        (spans, next)
+    }
+}
+
+/// The parser state at the start of each line of one buffer, worked out as
+/// far as it has been needed.
+#[derive(Clone, Default)]
+pub struct Highlighter {
+    language: Language,
+    /// State at the start of each line, starting with line 0. Empty for plain text.
+    states: Vec<LineState>,
+    /// `states[..valid]` are up to date; later ones are from before the last
+    /// edits, moved to where their lines are now.
+    valid: usize,
+    /// Lines before this may have changed since their state was worked out, so
+    /// re-parsing cannot stop before it.
+    edited_to: usize,
+}
+
+impl Highlighter {
+    /// Records an edit that replaced `removed` line breaks after the start of
+    /// `line` with `added` ones.
+    pub fn edited(&mut self, line: usize, removed: usize, added: usize) {
+        if line + 1 < self.states.len() {
+            let end = (line + 1 + removed).min(self.states.len());
+            let placeholder = self.states[line].clone();
+            self.states.splice(line + 1..end, std::iter::repeat_n(placeholder, added));
+        }
+        self.valid = self.valid.min(line + 1);
+        let shifted = if self.edited_to > line + removed { self.edited_to + added - removed } else { 0 };
+        self.edited_to = shifted.max(line + added + 1);
+    }
+
+    /// The state at the start of `line` of `text`, parsing from the last line
+    /// known to be up to date. Past the edited lines, parsing stops as soon as
+    /// a line starts in the same state as before, since everything after it
+    /// parses as it did.
+    ///
+    /// `None` for plain text, and when getting there takes past `deadline`;
+    /// calling again carries on where this call stopped.
+    pub fn state_at(&mut self, language: Language, text: &Rope, line: usize, deadline: Instant) -> Option<LineState> {
+        if language != self.language || self.states.is_empty() {
+            let states = LineState::start(language).into_iter().collect();
+            *self = Self { language, states, valid: 1, edited_to: 0 };
+        }
+        if self.states.is_empty() {
+            return None;
+        }
+        let line = line.min(text.len_lines() - 1);
+        while self.valid <= line {
+            let previous = self.valid - 1;
+            let (_, state) = self.states[previous].parse(&Cow::from(text.line(previous)));
+            if self.valid < self.states.len() {
+                if self.valid >= self.edited_to && self.states[self.valid] == state {
+                    self.valid = self.states.len();
+                    self.edited_to = 0;
+                    break;
+                }
+                self.states[self.valid] = state;
+            } else {
+                self.states.push(state);
+            }
+            self.valid += 1;
+            if self.valid <= line && Instant::now() >= deadline {
+                return None;
+            }
+        }
+        Some(self.states[line].clone())
+    }
+}
+
+/// Spans of the lines the editor laid out last, so laying the same lines out
+/// again, as the editor does every frame, does not parse them again.
+#[derive(Default)]
+pub struct SpanCache {
+    lines: HashMap<String, Vec<CachedLine>>,
+}
+
+struct CachedLine {
+    start: LineState,
+    spans: Vec<(Range<usize>, TokenKind)>,
+    next: LineState,
+}
+
+impl SpanCache {
+    /// Highlighted spans of `text`, which starts at the start of a line in
+    /// `state`, as byte ranges into it, in order. Only the lines of this
+    /// `text` are kept for next time.
+    pub fn spans(&mut self, text: &str, state: &LineState) -> Vec<(Range<usize>, TokenKind)> {
+        let mut previous = std::mem::take(&mut self.lines);
+        let mut spans = Vec::new();
+        let mut state = state.clone();
+        let mut start = 0;
+        for line in text.split_inclusive('\n') {
+            let cached = previous.get_mut(line).and_then(|cached| {
+                let at = cached.iter().position(|cached| cached.start == state)?;
+                Some(cached.swap_remove(at))
+            });
+            let cached = cached.unwrap_or_else(|| {
+                let (spans, next) = state.spans(line);
+                CachedLine { start: state.clone(), spans, next }
+            });
+            spans.extend(cached.spans.iter().map(|(range, kind)| (start + range.start..start + range.end, *kind)));
+            state = cached.next.clone();
+            start += line.len();
+            self.lines.entry(line.to_owned()).or_default().push(cached);
+        }
+        spans
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn later() -> Instant {
+        Instant::now() + std::time::Duration::from_secs(60)
+    }
+
+    fn kinds(language: Language, text: &str) -> Vec<(&str, TokenKind)> {
+        let state = LineState::start(language).unwrap();
+        SpanCache::default().spans(text, &state).into_iter().map(|(range, kind)| (&text[range], kind)).collect()
+    }
+
+    #[test]
+    fn highlights_rust() {
+        let text = "fn main() { let s = \"a\\\"b\"; 'x'; }\n/* one\n /* nested */ two */ loop {}\nstruct S<'a>(&'a u8, bool); // done\nimpl X { fn f(&self) -> f64 { self.x + 1.5e3 as f64 + true as f64 } }";
+        assert_eq!(
+            kinds(Language::Rust, text),
+            vec![
+                ("fn", TokenKind::Keyword),
+                ("let", TokenKind::Keyword),
+                ("\"a\\\"b\"", TokenKind::String),
+                ("'x'", TokenKind::String),
+                ("/* one", TokenKind::Comment),
+                (" /* nested */ two */", TokenKind::Comment),
+                ("loop", TokenKind::Keyword),
+                ("struct", TokenKind::Keyword),
+                ("S", TokenKind::Type),
+                ("'a", TokenKind::Type),
+                ("'a", TokenKind::Type),
+                ("u8", TokenKind::Keyword),
+                ("bool", TokenKind::Keyword),
+                ("// done", TokenKind::Comment),
+                ("impl", TokenKind::Keyword),
+                ("fn", TokenKind::Keyword),
+                ("f64", TokenKind::Keyword),
+                ("self", TokenKind::Keyword),
+                ("1.5e3", TokenKind::Number),
+                ("as", TokenKind::Keyword),
+                ("f64", TokenKind::Keyword),
+                ("true", TokenKind::Constant),
+                ("as", TokenKind::Keyword),
+                ("f64", TokenKind::Keyword),
+            ]
+        );
+    }
+
+    #[test]
+    fn highlights_python() {
+        assert_eq!(
+            kinds(Language::Python, "def f(x: int) -> None:\n    s = \"\"\"one\n    two\"\"\"\n    return x and 0x1F # why\n"),
+            vec![
+                ("def", TokenKind::Keyword),
+                ("int", TokenKind::Type),
+                ("None", TokenKind::Constant),
+                ("\"\"\"one", TokenKind::String),
+                ("    two\"\"\"", TokenKind::String),
+                ("return", TokenKind::Keyword),
+                ("and", TokenKind::Keyword),
+                ("0x1F", TokenKind::Number),
+                ("# why", TokenKind::Comment),
+            ]
+        );
+    }
+
+    #[test]
+    fn highlights_javascript() {
+        assert_eq!(
+            kinds(Language::JavaScript, "class A extends B { m() { return `x ${this.y}` === null; } } // c\n/* a\nb */ let n = 42;"),
+            vec![
+                ("class", TokenKind::Keyword),
+                ("A", TokenKind::Type),
+                ("extends", TokenKind::Keyword),
+                ("B", TokenKind::Type),
+                ("return", TokenKind::Keyword),
+                ("`x ", TokenKind::String),
+                ("this", TokenKind::Keyword),
+                ("`", TokenKind::String),
+                ("null", TokenKind::Constant),
+                ("// c", TokenKind::Comment),
+                ("/* a", TokenKind::Comment),
+                ("b */", TokenKind::Comment),
+                ("let", TokenKind::Keyword),
+                ("42", TokenKind::Number),
+            ]
+        );
+    }
+
+    #[test]
+    fn highlights_c() {
+        assert_eq!(
+            kinds(Language::C, "#include <stdio.h>\nint main(void) { char c = 'x'; return 42UL; } /* c */"),
+            vec![
+                ("#include", TokenKind::Keyword),
+                ("<stdio.h>", TokenKind::String),
+                ("int", TokenKind::Keyword),
+                ("void", TokenKind::Keyword),
+                ("char", TokenKind::Keyword),
+                ("'x'", TokenKind::String),
+                ("return", TokenKind::Keyword),
+                ("42UL", TokenKind::Number),
+                ("/* c */", TokenKind::Comment),
+            ]
+        );
+    }
+
+    #[test]
+    fn highlights_json() {
+        assert_eq!(
+            kinds(Language::Json, "{\"a\": [1.5, true, null, \"s\"]}"),
+            vec![
+                ("\"a\"", TokenKind::Key),
+                ("1.5", TokenKind::Number),
+                ("true", TokenKind::Constant),
+                ("null", TokenKind::Constant),
+                ("\"s\"", TokenKind::String),
+            ]
+        );
+    }
+
+    #[test]
+    fn highlights_toml() {
+        let text = "[deps]\nname = \"x\\n\" # why\nserde.version = '1'\nlist = [1, 0x2f, true, 1979-05-27]\ninline = { a = \"\"\"one\ntwo\"\"\" }\n[[bin]]";
+        assert_eq!(
+            kinds(Language::Toml, text),
+            vec![
+                ("[deps]", TokenKind::Heading),
+                ("name", TokenKind::Key),
+                ("\"x\\n\"", TokenKind::String),
+                ("# why", TokenKind::Comment),
+                ("serde.version", TokenKind::Key),
+                ("'1'", TokenKind::String),
+                ("list", TokenKind::Key),
+                ("1", TokenKind::Number),
+                ("0x2f", TokenKind::Number),
+                ("true", TokenKind::Constant),
+                ("1979-05-27", TokenKind::Number),
+                ("inline", TokenKind::Key),
+                ("a", TokenKind::Key),
+                ("\"\"\"one", TokenKind::String),
+                ("two\"\"\"", TokenKind::String),
+                ("[[bin]]", TokenKind::Heading),
+            ]
+        );
+    }
+
+    #[test]
+    fn highlights_markdown() {
+        assert_eq!(
+            kinds(Language::Markdown, "# Title\n> quote\n\n- `code` [x](url)\n1. item\n\n```rust\nfn\n```"),
+            vec![
+                ("# Title", TokenKind::Heading),
+                ("> quote", TokenKind::Comment),
+                ("-", TokenKind::Keyword),
+                ("`code`", TokenKind::String),
+                ("url", TokenKind::Type),
+                ("1.", TokenKind::Keyword),
+                ("```rust", TokenKind::String),
+                ("fn", TokenKind::String),
+                ("```", TokenKind::String),
+            ]
+        );
+    }
+
+    #[test]
+    fn highlights_shell() {
+        assert_eq!(
+            kinds(Language::Shell, "if [ -n \"$HOME\" ]; then echo ${X} 'a' 42; fi # c"),
+            vec![
+                ("if", TokenKind::Keyword),
+                ("\"", TokenKind::String),
+                ("$HOME", TokenKind::Key),
+                ("\"", TokenKind::String),
+                ("then", TokenKind::Keyword),
+                ("${X}", TokenKind::Key),
+                ("'a'", TokenKind::String),
+                ("fi", TokenKind::Keyword),
+                ("# c", TokenKind::Comment),
+            ]
+        );
+    }
+
+    #[test]
+    fn plain_text_and_overlong_lines_are_not_highlighted() {
+        assert_eq!(LineState::start(Language::PlainText), None);
+        let long = format!("let s = \"{}\";\nlet t = 1;", "x".repeat(MAX_LINE_BYTES));
+        let kinds = kinds(Language::Rust, &long);
+        assert_eq!(kinds, vec![("let", TokenKind::Keyword), ("1", TokenKind::Number)]);
+    }
+
+    #[test]
+    fn cached_lines_are_reused_only_from_the_same_state() {
+        let state = LineState::start(Language::Rust).unwrap();
+        let mut cache = SpanCache::default();
+        let text = "a\n/* b\nc */\n";
+        let first = cache.spans(text, &state);
+        assert_eq!(cache.spans(text, &state), first);
+        // The same lines without the comment's opening are no longer inside it.
+        assert_eq!(cache.spans("a\nc */\n", &state), vec![]);
+        assert_eq!(cache.lines.len(), 2);
+    }
+
+    #[test]
+    fn parsing_past_the_deadline_carries_on_in_the_next_call() {
+        let text = Rope::from_str("/* a\nb */\nc\nd\n");
+        let mut highlighter = Highlighter::default();
+        let calls = std::iter::repeat_with(|| highlighter.state_at(Language::Rust, &text, 3, Instant::now()));
+        // One line a call once the deadline has passed.
+        assert_eq!(calls.take_while(Option::is_none).count(), 2);
+        assert_eq!(highlighter.valid, 4);
+        let mut fresh = Highlighter::default();
+        assert_eq!(highlighter.state_at(Language::Rust, &text, 3, Instant::now()), fresh.state_at(Language::Rust, &text, 3, later()));
+        assert_eq!(highlighter.state_at(Language::PlainText, &text, 3, later()), None);
+    }
+
+    #[test]
+    fn edits_reparse_only_until_the_state_settles() {
+        fn states(highlighter: &mut Highlighter, text: &Rope) -> Vec<LineState> {
+            (0..text.len_lines()).map(|line| highlighter.state_at(Language::Rust, text, line, later()).unwrap()).collect()
+        }
+        fn fresh(text: &Rope) -> Vec<LineState> {
+            states(&mut Highlighter::default(), text)
+        }
+
+        let mut text = Rope::from_str(&"let a = 1;\n".repeat(100));
+        let mut highlighter = Highlighter::default();
+        let before = states(&mut highlighter, &text);
+
+        // Opening a comment changes every line after it.
+        text.insert(text.line_to_char(1), "/* ");
+        highlighter.edited(1, 0, 0);
+        assert_ne!(highlighter.state_at(Language::Rust, &text, 2, later()).unwrap(), before[2]);
+        assert_eq!(states(&mut highlighter, &text), fresh(&text));
+
+        // Closing it again changes them all back.
+        text.insert(text.line_to_char(1) + 3, "x */");
+        highlighter.edited(1, 0, 0);
+        assert_eq!(states(&mut highlighter, &text), fresh(&text));
+
+        // An edit that leaves the state at the end of its line alone re-parses
+        // just that line.
+        text.insert(text.line_to_char(50), "y");
+        highlighter.edited(50, 0, 0);
+        highlighter.state_at(Language::Rust, &text, 51, later());
+        assert_eq!(highlighter.valid, highlighter.states.len());
+
+        // Lines joined and split keep the states after them in place.
+        let second = text.line_to_char(1);
+        text.remove(second..text.line_to_char(5));
+        highlighter.edited(1, 4, 0);
+        text.insert(text.line_to_char(10), "/* a\nb */\n");
+        highlighter.edited(10, 0, 2);
+        assert_eq!(highlighter.states.len(), text.len_lines());
+        assert_eq!(states(&mut highlighter, &text), fresh(&text));
+    }
+}
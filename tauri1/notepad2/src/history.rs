@@ -0,0 +1,198 @@
+//! Operation-based undo/redo history for [`FileBuffer`](crate::file_buffer::FileBuffer).
+//!
+//! Only the inserted or deleted text of each edit is kept, so memory grows
+//! with the size of the edits rather than the size of the file.
+
+/// A single reversible change, addressed in character indices.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EditOp {
+    Insert { at: usize, text: String },
+    Delete { at: usize, text: String },
+}
+
+impl EditOp {
+    /// The operation that reverts this one.
+    pub fn inverse(&self) -> EditOp {
+        match self {
+            EditOp::Insert { at, text } => EditOp::Delete { at: *at, text: text.clone() },
+            EditOp::Delete { at, text } => EditOp::Insert { at: *at, text: text.clone() },
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        match self {
+            EditOp::Insert { text, .. } | EditOp::Delete { text, .. } => text,
+        }
+    }
+
+    /// Cursor position right after this operation has been applied.
+    pub fn end_cursor(&self) -> usize {
+        match self {
+            EditOp::Insert { at, text } => at + text.chars().count(),
+            EditOp::Delete { at, .. } => *at,
+        }
+    }
+}
+
+/// Operations that are undone and redone together, with the cursor on either side.
+#[derive(Clone, Debug, Default)]
+pub struct EditGroup {
+    /// Identifies the document state reached by applying this group.
+    pub revision: u64,
+    pub ops: Vec<EditOp>,
+    pub cursor_before: usize,
+    pub cursor_after: usize,
+}
+
+impl EditGroup {
+    /// Memory held by the text of this group, in bytes.
+    #[cfg(test)]
+    pub fn text_len(&self) -> usize {
+        self.ops.iter().map(|op| op.text().len()).sum()
+    }
+}
+
+/// Multi-character inserts come from paste or programmatic replacement, not typing.
+fn is_paste(op: &EditOp) -> bool {
+    matches!(op, EditOp::Insert { text, .. } if text.chars().nth(1).is_some())
+}
+
+/// Whether `op` should begin a new undo step rather than extend `group`.
+fn starts_new_group(group: &EditGroup, op: &EditOp) -> bool {
+    let Some(last) = group.ops.last() else {
+        return false;
+    };
+    match (last, op) {
+        // A selection being replaced: the delete and the text typed or pasted over it
+        // form one step.
+        (EditOp::Delete { text: deleted, .. }, EditOp::Insert { at, .. }) => {
+            group.ops.len() > 1 || deleted.chars().nth(1).is_none() || *at != group.cursor_after
+        }
+        (EditOp::Insert { .. }, EditOp::Delete { .. }) => true,
+        (EditOp::Insert { text: typed, .. }, EditOp::Insert { at, text }) => {
+            if *at != group.cursor_after || is_paste(op) {
+                return true;
+            }
+            // Break at word boundaries: whitespace followed by the start of a new word.
+            let after_space = typed.chars().last().is_some_and(char::is_whitespace);
+            let starts_word = text.chars().next().is_some_and(|c| !c.is_whitespace());
+            after_space && starts_word
+        }
+        (EditOp::Delete { .. }, EditOp::Delete { at, text }) => {
+            // Backspace ends at the cursor; forward delete starts at it.
+            let backspace = at + text.chars().count() == group.cursor_after;
+            let forward = *at == group.cursor_after;
+            !(backspace || forward)
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct History {
+    undo_stack: Vec<EditGroup>,
+    redo_stack: Vec<EditGroup>,
+    pending: Option<EditGroup>,
+    last_revision: u64,
+}
+
+impl History {
+    /// Adds an operation to the open group, first closing it if the operation
+    /// starts a new logical edit. Any redo history is discarded.
+    pub fn record(&mut self, op: EditOp) {
+        self.redo_stack.clear();
+        if self.pending.as_ref().is_some_and(|group| starts_new_group(group, &op)) {
+            self.commit();
+        }
+        let is_paste = is_paste(&op);
+
+        let next_revision = self.last_revision + 1;
+        let group = self.pending.get_or_insert_with(|| EditGroup {
+            revision: next_revision,
+            cursor_before: match &op {
+                EditOp::Insert { at, .. } => *at,
+                EditOp::Delete { at, text } => at + text.chars().count(),
+            },
+            ..Default::default()
+        });
+        group.cursor_after = op.end_cursor();
+        self.last_revision = self.last_revision.max(group.revision);
+
+        // Consecutive typing extends the previous insert instead of adding a new op.
+        if let (Some(EditOp::Insert { at, text }), EditOp::Insert { at: next_at, text: next }) =
+            (group.ops.last_mut(), &op)
+            && *at + text.chars().count() == *next_at
+        {
+            text.push_str(next);
+        } else {
+            group.ops.push(op);
+        }
+
+        // Nothing typed afterwards should merge into a paste.
+        if is_paste {
+            self.commit();
+        }
+    }
+
+    /// Closes the open group so the next edit starts a new undo step.
+    pub fn commit(&mut self) {
+        if let Some(group) = self.pending.take()
+            && !group.ops.is_empty()
+        {
+            self.undo_stack.push(group);
+        }
+    }
+
+    /// Records a group built elsewhere as one undo step of its own.
+    pub fn push_group(&mut self, mut group: EditGroup) {
+        self.commit();
+        self.redo_stack.clear();
+        if !group.ops.is_empty() {
+            self.last_revision += 1;
+            group.revision = self.last_revision;
+            self.undo_stack.push(group);
+        }
+    }
+
+    /// Pops the most recent group for undoing and moves it onto the redo stack.
+    pub fn take_undo(&mut self) -> Option<EditGroup> {
+        self.commit();
+        let group = self.undo_stack.pop()?;
+        self.redo_stack.push(group.clone());
+        Some(group)
+    }
+
+    /// Pops the most recently undone group and moves it back onto the undo stack.
+    pub fn take_redo(&mut self) -> Option<EditGroup> {
+        self.commit();
+        let group = self.redo_stack.pop()?;
+        self.undo_stack.push(group.clone());
+        Some(group)
+    }
+
+    /// Revision of the current document state; 0 is the state the buffer was opened with.
+    pub fn revision(&self) -> u64 {
+        self.pending
+            .as_ref()
+            .or(self.undo_stack.last())
+            .map_or(0, |group| group.revision)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.pending.is_some() || !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    /// Bytes of edit text held across both stacks.
+    #[cfg(test)]
+    pub fn memory_usage(&self) -> usize {
+        self.undo_stack
+            .iter()
+            .chain(&self.redo_stack)
+            .chain(&self.pending)
+            .map(EditGroup::text_len)
+            .sum()
+    }
+}
@@ -0,0 +1,178 @@
+//! Keyboard shortcuts for editor commands, rebindable from the config file's
+//! `[keybindings]` table with values like `"Ctrl+Shift+Z"`.
+
+use eframe::egui::{Context, Key, KeyboardShortcut, Modifiers};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    Save,
+    Undo,
+    Redo,
+    Find,
+    FindNext,
+    FindPrevious,
+    GoToLine,
+    ZoomIn,
+    ZoomOut,
+    ZoomReset,
+}
+
+impl Action {
+    pub const ALL: [Action; 10] = [
+        Action::Save,
+        Action::Undo,
+        Action::Redo,
+        Action::Find,
+        Action::FindNext,
+        Action::FindPrevious,
+        Action::GoToLine,
+        Action::ZoomIn,
+        Action::ZoomOut,
+        Action::ZoomReset,
+    ];
+
+    /// The action's key in the `[keybindings]` table.
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Save => "save",
+            Action::Undo => "undo",
+            Action::Redo => "redo",
+            Action::Find => "find",
+            Action::FindNext => "find_next",
+            Action::FindPrevious => "find_previous",
+            Action::GoToLine => "go_to_line",
+            Action::ZoomIn => "zoom_in",
+            Action::ZoomOut => "zoom_out",
+            Action::ZoomReset => "zoom_reset",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|action| action.name() == name)
+    }
+
+    fn default_shortcuts(self) -> Vec<KeyboardShortcut> {
+        let command = |key| KeyboardShortcut::new(Modifiers::COMMAND, key);
+        match self {
+            Action::Save => vec![command(Key::S)],
+            Action::Undo => vec![command(Key::Z)],
+            Action::Redo => vec![command(Key::Y), KeyboardShortcut::new(Modifiers::COMMAND | Modifiers::SHIFT, Key::Z)],
+            Action::Find => vec![command(Key::F)],
+            Action::FindNext => vec![KeyboardShortcut::new(Modifiers::NONE, Key::F3)],
+            Action::FindPrevious => vec![KeyboardShortcut::new(Modifiers::SHIFT, Key::F3)],
+            Action::GoToLine => vec![command(Key::G)],
+            // Plus usually needs Shift, so the unshifted key works too.
+            Action::ZoomIn => vec![command(Key::Plus), command(Key::Equals)],
+            Action::ZoomOut => vec![command(Key::Minus)],
+            Action::ZoomReset => vec![command(Key::Num0)],
+        }
+    }
+}
+
+/// The shortcuts bound to each action. An action may have several, or none.
+#[derive(Clone, PartialEq)]
+pub struct Keybindings {
+    bindings: Vec<(Action, Vec<KeyboardShortcut>)>,
+}
+
+impl Default for Keybindings {
+    fn default() -> Self {
+        Self { bindings: Action::ALL.into_iter().map(|action| (action, action.default_shortcuts())).collect() }
+    }
+}
+
+impl Keybindings {
+    pub fn get(&self, action: Action) -> &[KeyboardShortcut] {
+        self.bindings.iter().find(|(a, _)| *a == action).map_or(&[], |(_, shortcuts)| shortcuts)
+    }
+
+    pub fn set(&mut self, action: Action, shortcuts: Vec<KeyboardShortcut>) {
+        if let Some((_, bound)) = self.bindings.iter_mut().find(|(a, _)| *a == action) {
+            *bound = shortcuts;
+        }
+    }
+
+    /// Consumes the shortcuts pressed this frame, so the editor does not also
+    /// act on them, and returns their actions.
+    pub fn pressed(&self, ctx: &Context) -> Vec<Action> {
+        // A shortcut also matches with extra Shift or Alt held, so try the ones
+        // with more modifiers first: Ctrl+Shift+Z must not be taken for Ctrl+Z.
+        let mut all: Vec<(Action, &KeyboardShortcut)> = self
+            .bindings
+            .iter()
+            .flat_map(|(action, shortcuts)| shortcuts.iter().map(move |shortcut| (*action, shortcut)))
+            .collect();
+        all.sort_by_key(|(_, shortcut)| std::cmp::Reverse(modifier_count(shortcut.modifiers)));
+        let mut actions = Vec::new();
+        ctx.input_mut(|input| {
+            for (action, shortcut) in all {
+                if input.consume_shortcut(shortcut) && !actions.contains(&action) {
+                    actions.push(action);
+                }
+            }
+        });
+        actions
+    }
+}
+
+fn modifier_count(modifiers: Modifiers) -> usize {
+    [modifiers.command, modifiers.shift, modifiers.alt].into_iter().filter(|&held| held).count()
+}
+
+/// Parses `"Ctrl+Shift+Z"`, `"F3"` and the like. `Ctrl` and `Cmd` both mean
+/// the platform's command key.
+pub fn parse_shortcut(text: &str) -> Result<KeyboardShortcut, String> {
+    let mut parts: Vec<&str> = text.split('+').map(str::trim).collect();
+    // "Ctrl++" binds the plus key.
+    if text.ends_with("++") {
+        parts.truncate(parts.len() - 2);
+        parts.push("+");
+    }
+    let (key, modifier_names) = parts.split_last().ok_or_else(|| format!("\"{}\" has no key", text))?;
+    let mut modifiers = Modifiers::NONE;
+    for name in modifier_names {
+        match name.to_ascii_lowercase().as_str() {
+            "ctrl" | "cmd" | "command" => modifiers |= Modifiers::COMMAND,
+            "shift" => modifiers |= Modifiers::SHIFT,
+            "alt" | "option" => modifiers |= Modifiers::ALT,
+            _ => return Err(format!("\"{}\" is not a modifier in \"{}\"", name, text)),
+        }
+    }
+    let key = Key::from_name(key)
+        .or_else(|| Key::from_name(&key.to_ascii_uppercase()))
+        .ok_or_else(|| format!("\"{}\" is not a key in \"{}\"", key, text))?;
+    Ok(KeyboardShortcut::new(modifiers, key))
+}
+
+/// The form `parse_shortcut` reads back.
+pub fn format_shortcut(shortcut: &KeyboardShortcut) -> String {
+    let mut text = String::new();
+    let modifiers = shortcut.modifiers;
+    for (held, name) in [(modifiers.command, "Ctrl"), (modifiers.shift, "Shift"), (modifiers.alt, "Alt")] {
+        if held {
+            text.push_str(name);
+            text.push('+');
+        }
+    }
+    text.push_str(shortcut.logical_key.name());
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shortcuts_round_trip_through_text() {
+        let redo = parse_shortcut("ctrl+shift+z").unwrap();
+        assert_eq!(redo, KeyboardShortcut::new(Modifiers::COMMAND | Modifiers::SHIFT, Key::Z));
+        assert_eq!(format_shortcut(&redo), "Ctrl+Shift+Z");
+        assert_eq!(parse_shortcut("F3").unwrap(), KeyboardShortcut::new(Modifiers::NONE, Key::F3));
+        assert_eq!(parse_shortcut("Ctrl++").unwrap(), KeyboardShortcut::new(Modifiers::COMMAND, Key::Plus));
+        for shortcut in Action::ALL.into_iter().flat_map(Action::default_shortcuts) {
+            assert_eq!(parse_shortcut(&format_shortcut(&shortcut)), Ok(shortcut));
+        }
+        assert!(parse_shortcut("Hyper+S").is_err());
+        assert!(parse_shortcut("Ctrl+Nope").is_err());
+    }
+}
@@ -0,0 +1,115 @@
+//! Language modes, picked from a file's extension or its `#!` line.
+
+use std::fmt;
+use std::path::Path;
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Language {
+    #[default]
+    PlainText,
+    Rust,
+    Python,
+    JavaScript,
+    C,
+    Json,
+    Toml,
+    Markdown,
+    Shell,
+}
+
+impl Language {
+    pub const ALL: [Language; 9] = [
+        Language::PlainText,
+        Language::Rust,
+        Language::Python,
+        Language::JavaScript,
+        Language::C,
+        Language::Json,
+        Language::Toml,
+        Language::Markdown,
+        Language::Shell,
+    ];
+
+    /// The mode for a file from its extension, falling back to the interpreter
+    /// named on a `#!` first line.
+    pub fn detect(path: Option<&str>, first_line: &str) -> Self {
+        match Self::from_path(path) {
+            Language::PlainText => Self::from_shebang(first_line),
+            language => language,
+        }
+    }
+
+    fn from_shebang(line: &str) -> Self {
+        let Some(command) = line.strip_prefix("#!") else {
+            return Language::PlainText;
+        };
+        // `#!/usr/bin/env python3` names the interpreter after `env`.
+        let mut words = command.split_whitespace().map(|word| word.rsplit('/').next().unwrap_or(word));
+        let interpreter = match words.next() {
+            Some("env") => words.find(|word| !word.starts_with('-')),
+            first => first,
+        };
+        match interpreter.unwrap_or_default().trim_end_matches(|c: char| c.is_ascii_digit() || c == '.') {
+            "python" => Language::Python,
+            "sh" | "bash" | "zsh" | "dash" | "ksh" => Language::Shell,
+            "node" | "deno" => Language::JavaScript,
+            _ => Language::PlainText,
+        }
+    }
+
+    /// The mode for a file, or plain text for untitled buffers and unknown extensions.
+    pub fn from_path(path: Option<&str>) -> Self {
+        let extension = path
+            .and_then(|path| Path::new(path).extension())
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("rs") => Language::Rust,
+            Some("py" | "pyw") => Language::Python,
+            Some("js" | "mjs" | "cjs" | "jsx" | "ts" | "tsx") => Language::JavaScript,
+            Some("c" | "h" | "cc" | "cpp" | "cxx" | "hpp") => Language::C,
+            Some("json") => Language::Json,
+            Some("toml") => Language::Toml,
+            Some("md" | "markdown") => Language::Markdown,
+            Some("sh" | "bash" | "zsh") => Language::Shell,
+            _ => Language::PlainText,
+        }
+    }
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Language::PlainText => "Plain Text",
+            Language::Rust => "Rust",
+            Language::Python => "Python",
+            Language::JavaScript => "JavaScript",
+            Language::C => "C/C++",
+            Language::Json => "JSON",
+            Language::Toml => "TOML",
+            Language::Markdown => "Markdown",
+            Language::Shell => "Shell",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_the_mode_from_the_extension() {
+        assert_eq!(Language::from_path(Some("/src/main.RS")), Language::Rust);
+        assert_eq!(Language::from_path(Some("notes.txt")), Language::PlainText);
+        assert_eq!(Language::from_path(Some("Makefile")), Language::PlainText);
+        assert_eq!(Language::from_path(None), Language::PlainText);
+    }
+
+    #[test]
+    fn falls_back_to_the_shebang() {
+        assert_eq!(Language::detect(Some("deploy"), "#!/usr/bin/env python3.11"), Language::Python);
+        assert_eq!(Language::detect(Some("run"), "#!/bin/bash -e"), Language::Shell);
+        assert_eq!(Language::detect(None, "#!/usr/bin/env -S node"), Language::JavaScript);
+        assert_eq!(Language::detect(Some("a.rs"), "#!/bin/sh"), Language::Rust);
+        assert_eq!(Language::detect(None, "plain"), Language::PlainText);
+    }
+}
@@ -0,0 +1,165 @@
+//! Line-ending styles. Buffers always hold `\n`; a file's own style is
+//! detected on open and written back on save.
+
+use std::borrow::Cow;
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl LineEnding {
+    pub const ALL: [LineEnding; 3] = [LineEnding::Lf, LineEnding::CrLf, LineEnding::Cr];
+
+    /// Used for new files and files with no line breaks.
+    #[cfg(windows)]
+    pub const NATIVE: LineEnding = LineEnding::CrLf;
+    #[cfg(not(windows))]
+    pub const NATIVE: LineEnding = LineEnding::Lf;
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+
+    /// Turns the buffer's `\n`s into this style for writing.
+    pub fn apply(self, text: &str) -> Cow<'_, str> {
+        if self == LineEnding::Lf || !text.contains('\n') {
+            Cow::Borrowed(text)
+        } else {
+            Cow::Owned(text.replace('\n', self.as_str()))
+        }
+    }
+}
+
+impl fmt::Display for LineEnding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LineEnding::Lf => "LF",
+            LineEnding::CrLf => "CRLF",
+            LineEnding::Cr => "CR",
+        })
+    }
+}
+
+/// The style a file is saved with, and whether it was mixed when read.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct LineEndings {
+    pub style: LineEnding,
+    /// Set when the file on disk used more than one style. Saving writes
+    /// `style` throughout, and callers report that with [`Self::conversion_note`].
+    pub mixed: bool,
+}
+
+impl Default for LineEndings {
+    fn default() -> Self {
+        Self { style: LineEnding::NATIVE, mixed: false }
+    }
+}
+
+impl fmt::Display for LineEndings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.mixed {
+            write!(f, "{} (mixed)", self.style)
+        } else {
+            write!(f, "{}", self.style)
+        }
+    }
+}
+
+impl LineEndings {
+    /// What the next save will do to a file read with mixed endings, for the status bar.
+    pub fn conversion_note(&self) -> Option<String> {
+        self.mixed.then(|| format!("mixed line endings converted to {}", self.style))
+    }
+
+    /// Detects the line endings of freshly read text and converts them all to `\n`.
+    /// The most common style wins; ties go to LF, then CRLF.
+    pub fn normalize(text: &str) -> (Cow<'_, str>, Self) {
+        let crlf = text.matches("\r\n").count();
+        let cr = text.matches('\r').count() - crlf;
+        let lf = text.matches('\n').count() - crlf;
+        let counts = [(LineEnding::Lf, lf), (LineEnding::CrLf, crlf), (LineEnding::Cr, cr)];
+
+        let used = counts.iter().filter(|(_, n)| *n > 0).count();
+        let most = counts.iter().map(|(_, n)| *n).max().unwrap_or(0);
+        let style = match counts.iter().find(|(_, n)| *n == most) {
+            Some(&(style, n)) if n > 0 => style,
+            _ => LineEnding::NATIVE,
+        };
+        let endings = Self { style, mixed: used > 1 };
+        (normalize_str(text), endings)
+    }
+}
+
+/// Converts `\r\n` and lone `\r` to `\n`, e.g. in pasted text.
+pub fn normalize_str(text: &str) -> Cow<'_, str> {
+    if text.contains('\r') {
+        Cow::Owned(text.replace("\r\n", "\n").replace('\r', "\n"))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+/// Splits text as read from disk into lines, each with the line ending it
+/// ends in; the last line's is empty when the text does not end in one.
+pub fn split_lines(text: &str) -> impl Iterator<Item = (&str, &str)> {
+    let mut rest = text;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let (line, ending) = match rest.find(['\r', '\n']) {
+            Some(end) if rest[end..].starts_with("\r\n") => (&rest[..end], &rest[end..end + 2]),
+            Some(end) => (&rest[..end], &rest[end..end + 1]),
+            None => (rest, ""),
+        };
+        rest = &rest[line.len() + ending.len()..];
+        Some((line, ending))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_and_normalizes_each_style() {
+        let (text, endings) = LineEndings::normalize("a\r\nb\r\nc");
+        assert_eq!(text, "a\nb\nc");
+        assert_eq!(endings, LineEndings { style: LineEnding::CrLf, mixed: false });
+        assert_eq!(endings.style.apply(&text), "a\r\nb\r\nc");
+
+        let (text, endings) = LineEndings::normalize("a\rb\r");
+        assert_eq!(text, "a\nb\n");
+        assert_eq!(endings.style, LineEnding::Cr);
+
+        let (_, endings) = LineEndings::normalize("no breaks");
+        assert_eq!(endings, LineEndings::default());
+    }
+
+    #[test]
+    fn mixed_files_use_the_most_common_style() {
+        let (text, endings) = LineEndings::normalize("a\r\nb\nc\r\nd\re");
+        assert_eq!(text, "a\nb\nc\nd\ne");
+        assert_eq!(endings, LineEndings { style: LineEnding::CrLf, mixed: true });
+        assert_eq!(endings.to_string(), "CRLF (mixed)");
+        assert_eq!(endings.conversion_note().as_deref(), Some("mixed line endings converted to CRLF"));
+        assert_eq!(LineEndings::default().conversion_note(), None);
+    }
+
+    #[test]
+    fn splits_lines_with_their_own_endings() {
+        let lines: Vec<_> = split_lines("a\r\nb\nc\rd").collect();
+        assert_eq!(lines, [("a", "\r\n"), ("b", "\n"), ("c", "\r"), ("d", "")]);
+        let lines: Vec<_> = split_lines("\n\r\n").collect();
+        assert_eq!(lines, [("", "\n"), ("", "\r\n")]);
+        assert_eq!(split_lines("").count(), 0);
+    }
+}
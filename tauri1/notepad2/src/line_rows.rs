@@ -0,0 +1,422 @@
+//! How many screen rows each line takes when long lines wrap, so the editor
+//! can size and place the lines it does not lay out.
+//!
+//! Lines the editor has laid out keep the row count it measured; the rest are
+//! estimated from their length. Without wrapping every line is one row and
+//! nothing is stored.
+//!
+//! The counts sit in the leaves of a treap, a binary tree kept balanced by
+//! random priorities, where each node also sums the lines and rows beneath it.
+//! Looking up a line or row, recording a measurement and splicing in edited
+//! lines all take O(log n), however many lines the text has.
+
+use ropey::Rope;
+
+/// Lines in each leaf built from a whole text. An edit may grow a leaf in
+/// place up to twice this; larger edits build new leaves instead.
+const LEAF_LINES: usize = 256;
+
+#[derive(Clone, Default)]
+pub struct LineRows {
+    /// Counts for every line, in line order. Empty when not wrapping.
+    root: Option<Box<Node>>,
+    /// Wrap width and average character width the rows were fitted for.
+    fitted: Option<(f32, f32)>,
+    /// State of the generator that picks node priorities.
+    seed: u64,
+}
+
+#[derive(Clone)]
+struct Node {
+    /// Characters in each of the leaf's lines, line break excluded.
+    chars: Vec<u32>,
+    /// Rows each of the leaf's lines takes at the fitted width.
+    rows: Vec<u32>,
+    priority: u64,
+    left: Option<Box<Node>>,
+    right: Option<Box<Node>>,
+    /// Lines in this subtree, this node's leaf included.
+    total_lines: usize,
+    /// Rows in this subtree, this node's leaf included.
+    total_rows: usize,
+}
+
+impl Node {
+    fn new(chars: Vec<u32>, rows: Vec<u32>, priority: u64) -> Box<Self> {
+        let mut node = Box::new(Self { chars, rows, priority, left: None, right: None, total_lines: 0, total_rows: 0 });
+        node.update();
+        node
+    }
+
+    /// Rows of this node's own leaf.
+    fn leaf_rows(&self) -> usize {
+        self.total_rows - total_rows(&self.left) - total_rows(&self.right)
+    }
+
+    /// Recomputes the totals after the leaf or a child changed.
+    fn update(&mut self) {
+        self.total_lines = total_lines(&self.left) + self.chars.len() + total_lines(&self.right);
+        let own: usize = self.rows.iter().map(|&rows| rows as usize).sum();
+        self.total_rows = total_rows(&self.left) + own + total_rows(&self.right);
+    }
+}
+
+impl LineRows {
+    /// Gets ready to answer for `text` wrapped at `wrap_width`, which is
+    /// infinite when lines do not wrap. Estimates are redone when the widths
+    /// change, dropping what was measured at the old ones.
+    pub fn fit(&mut self, text: &Rope, wrap_width: f32, char_width: f32) {
+        let widths = Some((wrap_width, char_width));
+        if self.fitted == widths && (self.root.is_none() || total_lines(&self.root) == text.len_lines()) {
+            return;
+        }
+        self.fitted = widths;
+        if wrap_width.is_infinite() {
+            self.root = None;
+            return;
+        }
+        if total_lines(&self.root) == text.len_lines() {
+            if let Some(root) = &mut self.root {
+                reestimate(root, widths);
+            }
+        } else {
+            let chars = (0..text.len_lines()).map(|line| line_chars(text, line)).collect();
+            self.root = self.build(chars);
+        }
+    }
+
+    /// Keeps the counts in step with an edit of `text`, which now has `added`
+    /// lines after `line` where it had `removed`. The lines it touched are
+    /// estimated again.
+    pub fn edited(&mut self, text: &Rope, line: usize, removed: usize, added: usize) {
+        if self.root.is_none() {
+            return;
+        }
+        let end = (line + 1 + removed).min(total_lines(&self.root));
+        let chars: Vec<u32> = (line..=line + added).map(|line| line_chars(text, line)).collect();
+        if let Some(root) = &mut self.root
+            && splice(root, line, end, &chars, self.fitted)
+        {
+            return;
+        }
+        let (before, rest) = split(self.root.take(), line, &mut self.seed);
+        let (_, after) = split(rest, end - line, &mut self.seed);
+        let middle = self.build(chars);
+        self.root = merge(merge(before, middle), after);
+    }
+
+    /// Records the rows `line` was laid out on, returning how many more (or
+    /// fewer) that is than was assumed.
+    pub fn measured(&mut self, line: usize, rows: u32) -> i64 {
+        match &mut self.root {
+            Some(root) if line < root.total_lines => measure(root, line, rows.max(1)),
+            _ => 0,
+        }
+    }
+
+    /// Rows taken by `line`.
+    pub fn rows(&self, line: usize) -> usize {
+        let mut node = &self.root;
+        let mut line = line;
+        while let Some(n) = node {
+            let left = total_lines(&n.left);
+            if line < left {
+                node = &n.left;
+            } else if line - left < n.rows.len() {
+                return n.rows[line - left] as usize;
+            } else {
+                line -= left + n.rows.len();
+                node = &n.right;
+            }
+        }
+        1
+    }
+
+    /// Rows taken by the lines before `line`.
+    pub fn rows_before(&self, line: usize) -> usize {
+        if self.root.is_none() {
+            return line;
+        }
+        let mut node = &self.root;
+        let mut line = line;
+        let mut before = 0;
+        while let Some(n) = node {
+            let left = total_lines(&n.left);
+            if line <= left {
+                node = &n.left;
+                continue;
+            }
+            before += total_rows(&n.left);
+            line -= left;
+            if line <= n.rows.len() {
+                return before + n.rows[..line].iter().map(|&rows| rows as usize).sum::<usize>();
+            }
+            before += n.leaf_rows();
+            line -= n.rows.len();
+            node = &n.right;
+        }
+        before
+    }
+
+    /// The line shown on `row`, or the last line past the end of the text.
+    pub fn line_at_row(&self, row: usize, len_lines: usize) -> usize {
+        let Some(root) = &self.root else {
+            return row.min(len_lines.saturating_sub(1));
+        };
+        if row >= root.total_rows {
+            return root.total_lines - 1;
+        }
+        let mut node = &self.root;
+        let mut row = row;
+        let mut first = 0;
+        while let Some(n) = node {
+            let left = total_rows(&n.left);
+            if row < left {
+                node = &n.left;
+                continue;
+            }
+            row -= left;
+            first += total_lines(&n.left);
+            for (i, &rows) in n.rows.iter().enumerate() {
+                if row < rows as usize {
+                    return first + i;
+                }
+                row -= rows as usize;
+            }
+            first += n.rows.len();
+            node = &n.right;
+        }
+        root.total_lines - 1
+    }
+
+    /// A tree of leaves holding `chars`, with rows estimated at the fitted width.
+    fn build(&mut self, chars: Vec<u32>) -> Option<Box<Node>> {
+        let mut root = None;
+        for leaf in chars.chunks(LEAF_LINES) {
+            let rows = leaf.iter().map(|&chars| estimate(self.fitted, chars)).collect();
+            root = merge(root, Some(Node::new(leaf.to_vec(), rows, next_priority(&mut self.seed))));
+        }
+        root
+    }
+}
+
+fn total_lines(node: &Option<Box<Node>>) -> usize {
+    node.as_ref().map_or(0, |node| node.total_lines)
+}
+
+fn total_rows(node: &Option<Box<Node>>) -> usize {
+    node.as_ref().map_or(0, |node| node.total_rows)
+}
+
+/// Joins two trees, every line of `a` coming before those of `b`.
+fn merge(a: Option<Box<Node>>, b: Option<Box<Node>>) -> Option<Box<Node>> {
+    match (a, b) {
+        (None, node) | (node, None) => node,
+        (Some(mut a), Some(mut b)) => {
+            if a.priority >= b.priority {
+                a.right = merge(a.right.take(), Some(b));
+                a.update();
+                Some(a)
+            } else {
+                b.left = merge(Some(a), b.left.take());
+                b.update();
+                Some(b)
+            }
+        }
+    }
+}
+
+/// Splits a tree into its first `lines` lines and the rest, cutting the leaf
+/// the split falls in two if needed.
+fn split(node: Option<Box<Node>>, lines: usize, seed: &mut u64) -> (Option<Box<Node>>, Option<Box<Node>>) {
+    let Some(mut node) = node else {
+        return (None, None);
+    };
+    let left = total_lines(&node.left);
+    if lines <= left {
+        let (a, b) = split(node.left.take(), lines, seed);
+        node.left = b;
+        node.update();
+        return (a, Some(node));
+    }
+    let own = node.chars.len();
+    if lines >= left + own {
+        let (a, b) = split(node.right.take(), lines - left - own, seed);
+        node.right = a;
+        node.update();
+        return (Some(node), b);
+    }
+    let cut = lines - left;
+    let tail = Node::new(node.chars.split_off(cut), node.rows.split_off(cut), next_priority(seed));
+    let right = node.right.take();
+    node.update();
+    (Some(node), merge(Some(tail), right))
+}
+
+/// Replaces lines `start..end` with `chars` when they all sit in one leaf that
+/// stays small enough, returning whether it did.
+fn splice(node: &mut Node, start: usize, end: usize, chars: &[u32], fitted: Option<(f32, f32)>) -> bool {
+    let left = total_lines(&node.left);
+    let own = node.chars.len();
+    let done = if start < left {
+        end <= left && node.left.as_mut().is_some_and(|child| splice(child, start, end, chars, fitted))
+    } else if start < left + own {
+        let range = start - left..end - left;
+        if end > left + own || own - range.len() + chars.len() > 2 * LEAF_LINES {
+            return false;
+        }
+        node.chars.splice(range.clone(), chars.iter().copied());
+        node.rows.splice(range, chars.iter().map(|&chars| estimate(fitted, chars)));
+        true
+    } else {
+        let offset = left + own;
+        node.right.as_mut().is_some_and(|child| splice(child, start - offset, end - offset, chars, fitted))
+    };
+    if done {
+        node.update();
+    }
+    done
+}
+
+fn measure(node: &mut Node, line: usize, rows: u32) -> i64 {
+    let left = total_lines(&node.left);
+    let change = if line < left {
+        node.left.as_mut().map_or(0, |child| measure(child, line, rows))
+    } else if line - left < node.rows.len() {
+        i64::from(rows) - i64::from(std::mem::replace(&mut node.rows[line - left], rows))
+    } else {
+        let offset = left + node.rows.len();
+        node.right.as_mut().map_or(0, |child| measure(child, line - offset, rows))
+    };
+    node.total_rows = (node.total_rows as i64 + change) as usize;
+    change
+}
+
+fn reestimate(node: &mut Node, fitted: Option<(f32, f32)>) {
+    for child in [&mut node.left, &mut node.right].into_iter().flatten() {
+        reestimate(child, fitted);
+    }
+    node.rows = node.chars.iter().map(|&chars| estimate(fitted, chars)).collect();
+    node.update();
+}
+
+fn estimate(fitted: Option<(f32, f32)>, chars: u32) -> u32 {
+    match fitted {
+        Some((wrap_width, char_width)) if wrap_width > 0.0 => {
+            (chars as f32 * char_width / wrap_width).ceil().max(1.0) as u32
+        }
+        _ => 1,
+    }
+}
+
+/// A node priority from splitmix64.
+fn next_priority(seed: &mut u64) -> u64 {
+    *seed = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *seed;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+fn line_chars(text: &Rope, line: usize) -> u32 {
+    let line = text.line(line);
+    let mut len = line.len_chars();
+    for terminator in ['\n', '\r'] {
+        if len > 0 && line.char(len - 1) == terminator {
+            len -= 1;
+        }
+    }
+    len as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_lines_are_estimated_then_measured_and_follow_edits() {
+        // Ten characters to a row.
+        let mut text = Rope::from_str("short\n{}\n\nend");
+        let long = "x".repeat(35);
+        text.remove(6..8);
+        text.insert(6, &long);
+        let mut rows = LineRows::default();
+        rows.fit(&text, 100.0, 10.0);
+        assert_eq!((0..4).map(|line| rows.rows(line)).collect::<Vec<_>>(), [1, 4, 1, 1]);
+        assert_eq!(rows.rows_before(2), 5);
+        assert_eq!(rows.rows_before(4), 7);
+        assert_eq!([0, 1, 4, 5, 6, 99].map(|row| rows.line_at_row(row, 4)), [0, 1, 1, 2, 3, 3]);
+
+        // The editor found the long line on three rows.
+        assert_eq!(rows.measured(1, 3), -1);
+        assert_eq!(rows.rows_before(4), 6);
+
+        // Splitting the long line re-estimates both halves.
+        text.insert_char(6 + 20, '\n');
+        rows.edited(&text, 1, 0, 1);
+        assert_eq!((0..5).map(|line| rows.rows(line)).collect::<Vec<_>>(), [1, 2, 2, 1, 1]);
+        // Joining it with the next line again.
+        text.remove(6 + 20..6 + 21);
+        rows.edited(&text, 1, 1, 0);
+        assert_eq!((0..4).map(|line| rows.rows(line)).collect::<Vec<_>>(), [1, 4, 1, 1]);
+
+        // A new width starts over from estimates.
+        rows.fit(&text, 50.0, 10.0);
+        assert_eq!(rows.rows(1), 7);
+    }
+
+    #[test]
+    fn unwrapped_lines_are_one_row_each() {
+        let text = Rope::from_str(&format!("{}\nb\nc", "a".repeat(1000)));
+        let mut rows = LineRows::default();
+        rows.fit(&text, f32::INFINITY, 10.0);
+        assert_eq!(rows.rows(0), 1);
+        assert_eq!(rows.rows_before(3), 3);
+        assert_eq!(rows.line_at_row(7, 3), 2);
+        assert_eq!(rows.measured(0, 1), 0);
+    }
+
+    #[test]
+    fn edits_across_many_leaves_match_a_fresh_fit() {
+        let mut seed = 7;
+        let mut random = |below: usize| next_priority(&mut seed) as usize % below;
+        let lines: Vec<String> = (0..3000).map(|i| "y".repeat(i * 7 % 45)).collect();
+        let mut text = Rope::from_str(&lines.join("\n"));
+        let mut rows = LineRows::default();
+        rows.fit(&text, 100.0, 10.0);
+        for step in 0..300 {
+            let at = random(text.len_chars() + 1);
+            let line = text.char_to_line(at);
+            if step % 3 == 0 {
+                // Deletions, some of them spanning several leaves.
+                let len = random(if step % 30 == 0 { 20_000 } else { 40 }).min(text.len_chars() - at);
+                let removed = text.char_to_line(at + len) - line;
+                text.remove(at..at + len);
+                rows.edited(&text, line, removed, 0);
+            } else {
+                // Typing, new lines and pastes large enough to need new leaves.
+                let count = if step % 25 == 0 { 700 } else { random(3) };
+                let inserted = format!("{}{}", "z\nzzzzzzzzzzzzzzzz".repeat(count), "w".repeat(random(30)));
+                text.insert(at, &inserted);
+                rows.edited(&text, line, 0, count);
+            }
+        }
+        let mut fresh = LineRows::default();
+        fresh.fit(&text, 100.0, 10.0);
+        let len = text.len_lines();
+        for line in 0..=len {
+            assert_eq!(rows.rows(line), fresh.rows(line), "rows of line {}", line);
+            assert_eq!(rows.rows_before(line), fresh.rows_before(line), "rows before line {}", line);
+        }
+        for row in 0..fresh.rows_before(len) + 2 {
+            assert_eq!(rows.line_at_row(row, len), fresh.line_at_row(row, len), "line at row {}", row);
+        }
+
+        // A measurement moves everything after it.
+        let line = len / 2;
+        let change = rows.measured(line, 9);
+        assert_eq!(change, 9 - fresh.rows(line) as i64);
+        assert_eq!(rows.rows_before(len) as i64, fresh.rows_before(len) as i64 + change);
+        assert_eq!(rows.rows_before(line + 1) as i64, fresh.rows_before(line + 1) as i64 + change);
+    }
+}
@@ -1,6 +1,25 @@
 mod notepad_app;
+mod atomic_write;
+mod autosave;
+mod config;
+mod encoding;
 mod file_buffer;
+mod file_search;
+mod find;
+mod fonts;
+mod goto;
+mod highlight;
+mod history;
+mod keymap;
+mod language;
+mod line_ending;
+mod line_rows;
+mod recovery;
+mod results;
+mod search;
+mod session;
 mod editor;
+mod error;
 mod ui;
 mod theme;
 
@@ -9,10 +28,12 @@ use eframe::NativeOptions;
 use notepad_app::NotepadApp;
 
 fn main() -> Result<(), eframe::Error> {
+    // `--clean` starts with a single empty tab instead of reopening the last session's.
+    let clean = std::env::args().skip(1).any(|arg| arg == "--clean");
     let native_options = NativeOptions::default();
     eframe::run_native(
         "Rust Notepad",
         native_options,
-        Box::new(|_cc| Ok(Box::new(NotepadApp::default()))),
+        Box::new(move |cc| Ok(Box::new(NotepadApp::new(cc, clean)))),
     )
 }
@@ -1,31 +1,134 @@
+use crate::atomic_write::BackupMode;
+use crate::autosave::Autosave;
+use crate::config::{self, Config, ConfigFile};
 use crate::file_buffer::FileBuffer;
+use crate::file_search::FileSearch;
+use crate::find::{FindBar, FindScope};
+use crate::fonts::{self, FontFiles};
+use crate::goto::GotoDialog;
+use crate::highlight::SpanCache;
+use crate::keymap::Keybindings;
+use crate::recovery::{Journal, RecoveredBuffer, SwapDir};
+use crate::results::SearchResults;
+use crate::search::SearchOptions;
+use crate::session::{self, Session};
+use crate::theme::{self, SyntaxColors, Theme, ThemePreference};
 use crate::ui;
-use eframe::egui::{self, Context};
-use eframe::App;
+use eframe::egui::{Context, FontFamily, ViewportCommand};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use eframe::{App, CreationContext, Storage};
 
+/// Oldest entries are dropped past this many.
+const ERROR_LOG_LIMIT: usize = 200;
 
-#[derive(PartialEq)]
-pub enum ThemePreference {
-    System,
-    Light,
-    Dark,
+/// How long a status bar message stays up.
+const STATUS_TIMEOUT: Duration = Duration::from_secs(8);
+
+/// The transient message on the left of the status bar, kept apart from the
+/// buffer details on the right and cleared after `STATUS_TIMEOUT`.
+pub struct StatusMessage {
+    text: String,
+    shown_at: Instant,
+}
+
+impl StatusMessage {
+    pub fn new(text: String) -> Self {
+        Self { text, shown_at: Instant::now() }
+    }
+
+    /// Replaces the message and restarts its timer.
+    pub fn set(&mut self, text: String) {
+        *self = Self::new(text);
+    }
+
+    /// The message, unless it has expired, with how long it has left.
+    pub fn current(&self) -> Option<(&str, Duration)> {
+        let left = STATUS_TIMEOUT.checked_sub(self.shown_at.elapsed())?;
+        (!self.text.is_empty()).then_some((self.text.as_str(), left))
+    }
+}
+
+/// Something waiting on the unsaved-changes dialog.
+#[derive(Clone, Copy, PartialEq)]
+pub enum CloseRequest {
+    Tab(usize),
+    Exit,
 }
 
 pub struct NotepadApp {
     pub buffers: Vec<FileBuffer>,
     pub current_tab: usize,
+    /// Scale of the whole UI.
     pub font_scale: f32,
+    /// Editor text size in points.
+    pub font_size: f32,
     pub wrap_text: bool,
-    pub status: String,
+    pub show_line_numbers: bool,
+    /// Reopen the previous session's tabs on startup.
+    pub restore_tabs: bool,
+    /// Spaces inserted for Tab when `soft_tabs` is on.
+    pub tab_width: usize,
+    pub soft_tabs: bool,
+    /// Font family the editor text is drawn in, unless `font_files` has one.
+    pub font_family: FontFamily,
+    pub font_files: FontFiles,
+    /// The font files last handed to egui.
+    pub loaded_fonts: Option<FontFiles>,
+    pub keybindings: Keybindings,
+    /// The settings file, if the platform has a config directory.
+    pub config: Option<ConfigFile>,
+    pub status: StatusMessage,
+    /// Failed file operations, oldest first, for the error log panel.
+    pub error_log: Vec<String>,
+    pub show_error_log: bool,
     pub theme_pref: ThemePreference,
+    /// Shown instead of `theme_pref` while its menu entry is hovered.
+    pub theme_preview: Option<ThemePreference>,
+    /// Themes loaded from the themes directory, sorted by name.
+    pub themes: Vec<Theme>,
+    /// Syntax colors of the theme applied this frame.
+    pub syntax_colors: SyntaxColors,
+    /// Highlighted spans of the lines the editor laid out last.
+    pub span_cache: SpanCache,
     pub find_query: String,
     pub replace_query: String,
-    pub show_find: bool,
+    pub find_options: SearchOptions,
+    /// Why the current query cannot be searched for, shown in the find bar.
+    pub find_error: Option<String>,
+    /// Byte ranges of the matches in the current tab.
+    pub find_matches: Vec<Range<usize>>,
+    /// Index into `find_matches` of the selected match.
+    pub find_current: Option<usize>,
+    /// Buffer id and version `find_matches` were computed for.
+    pub find_searched: Option<(u64, u64)>,
+    /// Set while find and replace are limited to a selection.
+    pub find_scope: Option<FindScope>,
+    /// Matches from a search across tabs, shown in the results panel.
+    pub search_results: Option<SearchResults>,
+    /// The Find in Files search feeding `search_results`, if any.
+    pub file_search: Option<FileSearch>,
+    pub show_find_in_files: bool,
+    pub goto_dialog: Option<GotoDialog>,
+    /// Set to move keyboard focus into the editor on the next frame.
+    pub focus_editor: bool,
+    /// Folder the Find in Files dialog searches.
+    pub find_in_files_root: String,
+    pub find_bar: FindBar,
     pub editing_tab_index: Option<usize>,
     pub rename_buffer: String,
-    pub find_active: bool,
-    pub find_text: String,
-    pub find_cursor: usize,
+    pub pending_close: Option<CloseRequest>,
+    pub autosave: Autosave,
+    /// Copy kept of a file's previous version when saving over it.
+    pub backup: BackupMode,
+    pub journal: Journal,
+    /// Swap files from a previous session waiting for the user to restore or discard them.
+    pub recovered: Vec<RecoveredBuffer>,
+    /// Set when a setting is changed from the UI, until it is written to the config file.
+    pub settings_changed: bool,
+    /// Set once the user has agreed to exit, so the next close request goes through.
+    pub allow_close: bool,
 }
 
 impl Default for NotepadApp {
@@ -34,37 +137,273 @@ impl Default for NotepadApp {
             buffers: vec![FileBuffer::new("Untitled", String::new(), None)],
             current_tab: 0,
             font_scale: 1.0,
+            font_size: fonts::DEFAULT_FONT_SIZE,
             wrap_text: true,
-            status: "Welcome to Rust Notepad!".into(),
+            show_line_numbers: true,
+            restore_tabs: true,
+            tab_width: 4,
+            soft_tabs: false,
+            font_family: FontFamily::Monospace,
+            font_files: FontFiles::default(),
+            loaded_fonts: None,
+            keybindings: Keybindings::default(),
+            config: None,
+            status: StatusMessage::new("Welcome to Rust Notepad!".to_string()),
+            error_log: Vec::new(),
+            show_error_log: false,
             theme_pref: ThemePreference::System,
+            theme_preview: None,
+            themes: Vec::new(),
+            syntax_colors: SyntaxColors::DARK,
+            span_cache: SpanCache::default(),
             find_query: String::new(),
             replace_query: String::new(),
-            show_find: false,
+            find_options: SearchOptions::default(),
+            find_error: None,
+            find_matches: Vec::new(),
+            find_current: None,
+            find_searched: None,
+            find_scope: None,
+            search_results: None,
+            file_search: None,
+            show_find_in_files: false,
+            goto_dialog: None,
+            focus_editor: false,
+            find_in_files_root: String::new(),
+            find_bar: FindBar::default(),
             editing_tab_index: None,
             rename_buffer: String::new(),
-            find_active: false,
-            find_text: String::new(),
-            find_cursor: 0,
+            pending_close: None,
+            autosave: Autosave::default(),
+            backup: BackupMode::None,
+            journal: Journal::new(None),
+            recovered: Vec::new(),
+            settings_changed: false,
+            allow_close: false,
         }
     }
 }
 
 impl App for NotepadApp {
     fn update(&mut self, ctx: &Context, _frame: &mut eframe::Frame) {
-        let visuals = match self.theme_pref {
-            ThemePreference::System => egui::Visuals::default(),
-            ThemePreference::Light => egui::Visuals::light(),
-            ThemePreference::Dark => egui::Visuals::dark(),
-        };
-        ctx.set_visuals(visuals);
+        let theme = self.theme_preview.take().unwrap_or_else(|| self.theme_pref.clone());
+        self.syntax_colors = theme.apply(ctx, &self.themes);
         ctx.set_pixels_per_point(self.font_scale);
+        self.load_fonts(ctx);
+
+        if ctx.input(|i| i.viewport().close_requested()) && !self.allow_close {
+            ctx.send_viewport_cmd(ViewportCommand::CancelClose);
+            self.request_exit(ctx);
+        }
 
         ui::handle_keyboard_shortcuts(ctx, self);
-        // ui::autosave_current_tab(self);
+        if let Some(report) = self.autosave.run(&mut self.buffers) {
+            self.status.set(report);
+        }
+        if let Err(err) = self.journal.run(&self.buffers) {
+            self.status.set(format!("Could not write recovery data: {}", err));
+        }
+        if self.buffers.iter().any(FileBuffer::is_dirty) {
+            // Keep frames coming while idle so autosave and the recovery journal still run.
+            ctx.request_repaint_after(Duration::from_secs(1));
+        }
+        if self.config.as_ref().is_some_and(ConfigFile::changed_on_disk) {
+            self.load_config();
+        }
+        self.poll_file_search();
         ui::show_menu_bar(self, ctx);
         ui::show_find_replace(self, ctx);
         ui::show_status_bar(self, ctx);
+        ui::show_error_log(self, ctx);
+        ui::show_search_results(self, ctx);
         ui::show_tabs_and_editor(ctx, self);
-        // ui::draw_tabs_and_editor(ctx, self);
+        ui::show_find_in_files(self, ctx);
+        ui::show_goto_dialog(self, ctx);
+        ui::show_close_dialog(self, ctx);
+        ui::show_recovery_dialog(self, ctx);
+        // Wait for a slider drag to finish rather than writing the file every frame.
+        if self.settings_changed && !ctx.input(|i| i.pointer.any_down()) {
+            self.settings_changed = false;
+            self.save_config();
+        }
+    }
+
+    fn save(&mut self, storage: &mut dyn Storage) {
+        eframe::set_value(storage, session::STORAGE_KEY, &Session::capture(self));
+    }
+
+    fn persist_egui_memory(&self) -> bool {
+        // Buffer ids are reused across runs, so per-widget state would land on
+        // the wrong tabs; the session keeps cursor and scroll positions instead.
+        false
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        // Unsaved work was either saved or explicitly discarded on the way out.
+        self.journal.clear();
+    }
+}
+
+impl NotepadApp {
+    /// Starts from the config file's settings and, unless `clean` is set, the
+    /// last session's tabs and any swap files left by crashed instances. A
+    /// clean start still journals its own buffers but leaves those swaps for a
+    /// later start to offer.
+    pub fn new(cc: &CreationContext, clean: bool) -> Self {
+        let mut swap = SwapDir::open();
+        let recovered = match &mut swap {
+            Some(swap) if !clean => swap.leftovers(),
+            _ => Vec::new(),
+        };
+        let mut app = Self {
+            config: config::dir().map(|dir| ConfigFile::new(dir.join("config.toml"))),
+            journal: Journal::new(swap),
+            recovered,
+            ..Self::default()
+        };
+        app.load_config();
+        if let Some(file) = &mut app.config {
+            file.watch(cc.egui_ctx.clone());
+        }
+        // Ctrl+Plus/Minus/0 zoom the editor text, not the whole UI.
+        cc.egui_ctx.options_mut(|options| options.zoom_with_keyboard = false);
+        app.reload_themes();
+        if let Some(session) = cc.storage.and_then(|s| eframe::get_value::<Session>(s, session::STORAGE_KEY)) {
+            app.restore_session(session, !clean);
+        }
+        app
+    }
+
+    fn restore_session(&mut self, session: Session, reopen_tabs: bool) {
+        if !reopen_tabs || !self.restore_tabs {
+            return;
+        }
+
+        let (buffers, errors) = session.open_tabs();
+        let restored = buffers.len();
+        if restored > 0 {
+            self.current_tab = session.current_tab.min(restored - 1);
+            self.buffers = buffers;
+            self.status.set(format!("Restored {} tab(s) from last session", restored));
+        }
+        for err in errors {
+            self.report_error(format!("Could not reopen {}", err));
+        }
+    }
+
+    /// Reads the config file again, keeping the current settings if it cannot be parsed.
+    pub fn load_config(&mut self) {
+        let Some(file) = &mut self.config else {
+            return;
+        };
+        match file.load() {
+            Ok((config, errors)) => {
+                self.apply_config(&config);
+                for err in errors {
+                    self.report_error(format!("Invalid setting in {}", err));
+                }
+            }
+            Err(err) => self.report_error(format!("Could not read settings from {}", err)),
+        }
+    }
+
+    pub fn apply_config(&mut self, config: &Config) {
+        self.font_scale = config.font_scale;
+        self.font_size = config.font_size;
+        self.font_files = config.font_files.clone();
+        self.wrap_text = config.wrap_text;
+        self.show_line_numbers = config.line_numbers;
+        self.restore_tabs = config.restore_tabs;
+        self.theme_pref = config.theme.clone();
+        self.tab_width = config.tab_width;
+        self.soft_tabs = config.soft_tabs;
+        self.font_family = config.font_family.clone();
+        self.autosave.mode = config.autosave_mode;
+        self.autosave.delay = Duration::from_secs(config.autosave_secs);
+        self.backup = config.backup;
+        self.keybindings = config.keybindings.clone();
+    }
+
+    /// Writes settings changed since the config file was last read or written.
+    fn save_config(&mut self) {
+        let current = Config::capture(self);
+        let Some(file) = &mut self.config else {
+            return;
+        };
+        if current == file.saved {
+            return;
+        }
+        if let Err(err) = file.save(&current) {
+            self.report_error(err);
+        }
+    }
+
+    /// Opens the config file in a tab, first writing out the defaults if it
+    /// does not exist yet. Saving the tab applies the changes.
+    pub fn open_config_file(&mut self, path: PathBuf) {
+        let current = Config::capture(self);
+        if !path.exists()
+            && let Some(file) = &mut self.config
+            && let Err(err) = file.save_all(&current)
+        {
+            self.report_error(err);
+            return;
+        }
+        if let Some(index) = self.tab_for_path(&path) {
+            self.current_tab = index;
+            return;
+        }
+        match FileBuffer::open(&path) {
+            Ok(buffer) => {
+                self.buffers.push(buffer);
+                self.current_tab = self.buffers.len() - 1;
+            }
+            Err(err) => self.report_error(format!("Could not open {}", err)),
+        }
     }
-}
\ No newline at end of file
+
+    /// Hands egui the font files whenever they change. New fonts take effect
+    /// from the next frame.
+    fn load_fonts(&mut self, ctx: &Context) {
+        if self.loaded_fonts.as_ref() == Some(&self.font_files) {
+            return;
+        }
+        let (definitions, errors) = fonts::font_definitions(&self.font_files);
+        ctx.set_fonts(definitions);
+        self.loaded_fonts = Some(self.font_files.clone());
+        for err in errors {
+            self.report_error(format!("Could not load font {}", err));
+        }
+    }
+
+    /// Rereads the themes directory, logging files that fail to load.
+    pub fn reload_themes(&mut self) {
+        let Some(dir) = theme::themes_dir() else {
+            return;
+        };
+        let (themes, errors) = theme::load_themes(&dir);
+        self.themes = themes;
+        for err in errors {
+            self.report_error(format!("Could not load theme {}", err));
+        }
+    }
+
+    /// Shows an error in the status bar and keeps it in the error log.
+    pub fn report_error(&mut self, message: String) {
+        if self.error_log.len() >= ERROR_LOG_LIMIT {
+            self.error_log.remove(0);
+        }
+        self.error_log.push(message.clone());
+        self.status.set(message);
+    }
+
+    /// Closes the window, asking first if any tab has unsaved changes.
+    pub fn request_exit(&mut self, ctx: &Context) {
+        if self.buffers.iter().any(FileBuffer::is_dirty) {
+            self.pending_close = Some(CloseRequest::Exit);
+        } else {
+            self.allow_close = true;
+            ctx.send_viewport_cmd(ViewportCommand::Close);
+        }
+    }
+}
@@ -0,0 +1,455 @@
+//! Crash recovery: unsaved buffers are journaled to swap files so they survive
+//! a crash or kill, and leftovers are offered back on the next start.
+//!
+//! Swap files live in `$XDG_STATE_HOME/notepad2/swap` and are named
+//! `<session>-<buffer id>.swap`, where the session is the process id and its
+//! start time, so a later process given the same id never takes over a crashed
+//! one's swaps. Each instance holds an exclusive lock on `<session>.lock` there
+//! while it runs, and swaps are only offered back once their owner's lock can
+//! be taken. A swap whose owner left no lock file is never offered, since
+//! nothing proves that owner has exited.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+use ropey::Rope;
+
+use crate::file_buffer::FileBuffer;
+
+const JOURNAL_INTERVAL: Duration = Duration::from_secs(5);
+const HEADER: &str = "notepad2-swap 1";
+
+/// A buffer read back from a swap file left by an earlier session.
+pub struct RecoveredBuffer {
+    pub swap_path: PathBuf,
+    pub name: String,
+    pub file_path: Option<String>,
+    pub content: String,
+}
+
+impl RecoveredBuffer {
+    /// Turns the swap contents into a tab. The recovered text is applied as an
+    /// edit on top of the file on disk, so the tab shows as modified and undo
+    /// reveals the saved version.
+    pub fn into_buffer(self) -> FileBuffer {
+        let on_disk = self
+            .file_path
+            .as_ref()
+            .and_then(|path| FileBuffer::open(Path::new(path)).ok());
+        let mut buffer = on_disk.unwrap_or_else(|| FileBuffer::new(&self.name, String::new(), self.file_path));
+        buffer.name = self.name;
+        if *buffer.text() != self.content.as_str() {
+            buffer.set_content(&self.content);
+        }
+        buffer
+    }
+}
+
+pub struct SwapDir {
+    dir: PathBuf,
+    /// Names this instance's swap and lock files.
+    session: String,
+    /// This instance's lock, held until exit.
+    _lock: File,
+    /// Locks of exited instances whose swaps this one offered back, held so an
+    /// instance starting meanwhile does not offer the same swaps.
+    claimed: Vec<(String, File)>,
+}
+
+impl SwapDir {
+    /// The per-user swap directory, created if missing.
+    pub fn open() -> Option<Self> {
+        let base = dirs::state_dir().or_else(dirs::data_local_dir)?;
+        Self::at(base.join("notepad2").join("swap")).ok()
+    }
+
+    pub fn at(dir: PathBuf) -> io::Result<Self> {
+        let started = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+        Self::locked(dir, format!("{}_{}", std::process::id(), started.as_micros()))
+    }
+
+    fn locked(dir: PathBuf, session: String) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let lock = File::create(lock_path(&dir, &session))?;
+        lock.try_lock()?;
+        Ok(Self { dir, session, _lock: lock, claimed: Vec::new() })
+    }
+
+    fn path_for(&self, buffer_id: u64) -> PathBuf {
+        self.dir.join(format!("{}-{}.swap", self.session, buffer_id))
+    }
+
+    /// Takes what [`SwapWrite::write`] needs from the buffer. The rope is
+    /// shared rather than copied, so this is cheap however large the text is.
+    pub fn snapshot(&self, buffer: &FileBuffer) -> SwapWrite {
+        let header = format!(
+            "{}\nname: {}\npath: {}\n---\n",
+            HEADER,
+            buffer.name.replace('\n', " "),
+            buffer.file_path.as_deref().unwrap_or("")
+        );
+        SwapWrite { path: self.path_for(buffer.id), header, text: buffer.text().clone() }
+    }
+
+    pub fn remove(&self, buffer_id: u64) {
+        let _ = fs::remove_file(self.path_for(buffer_id));
+    }
+
+    /// Swap files left behind by instances that are no longer running. Their
+    /// locks stay claimed until [`Self::release`].
+    pub fn leftovers(&mut self) -> Vec<RecoveredBuffer> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let (swaps, locks): (Vec<PathBuf>, Vec<PathBuf>) = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "swap" || ext == "lock"))
+            .partition(|path| path.extension().is_some_and(|ext| ext == "swap"));
+        let mut found = Vec::new();
+        for path in swaps {
+            if owner(&path).is_some_and(|session| self.claim(session))
+                && let Some(recovered) = read_swap(&path)
+            {
+                found.push(recovered);
+            }
+        }
+        // Lock files of exited instances that left no swaps behind.
+        for path in locks {
+            if let Some(session) = owner(&path)
+                && session != self.session
+                && !self.claimed.iter().any(|(s, _)| *s == session)
+                && File::open(&path).is_ok_and(|lock| lock.try_lock().is_ok())
+            {
+                let _ = fs::remove_file(&path);
+            }
+        }
+        found.sort_by(|a, b| a.swap_path.cmp(&b.swap_path));
+        found
+    }
+
+    /// Takes the lock of another session, which succeeds only once it has
+    /// exited, even if it ran under the same process id as this one.
+    fn claim(&mut self, session: String) -> bool {
+        if session == self.session {
+            return false;
+        }
+        if self.claimed.iter().any(|(s, _)| *s == session) {
+            return true;
+        }
+        let Ok(lock) = File::open(lock_path(&self.dir, &session)) else {
+            return false;
+        };
+        if lock.try_lock().is_err() {
+            return false;
+        }
+        self.claimed.push((session, lock));
+        true
+    }
+
+    /// Removes this instance's lock file and those of claimed instances whose
+    /// swaps are all gone, on a clean exit.
+    pub fn release(&mut self) {
+        let remaining: Vec<String> = fs::read_dir(&self.dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "swap"))
+            .filter_map(|path| owner(&path))
+            .collect();
+        for (session, _) in std::mem::take(&mut self.claimed) {
+            if !remaining.contains(&session) {
+                let _ = fs::remove_file(lock_path(&self.dir, &session));
+            }
+        }
+        if !remaining.contains(&self.session) {
+            let _ = fs::remove_file(lock_path(&self.dir, &self.session));
+        }
+    }
+}
+
+/// A buffer's text and details, ready to be written to its swap file off the UI thread.
+pub struct SwapWrite {
+    path: PathBuf,
+    header: String,
+    text: Rope,
+}
+
+impl SwapWrite {
+    /// Streams the text chunk by chunk into a temporary file, then moves it
+    /// over any earlier swap for the buffer.
+    pub fn write(&self) -> io::Result<()> {
+        let tmp = self.path.with_extension("swap.tmp");
+        let mut file = BufWriter::new(File::create(&tmp)?);
+        file.write_all(self.header.as_bytes())?;
+        for chunk in self.text.chunks() {
+            file.write_all(chunk.as_bytes())?;
+        }
+        file.flush()?;
+        drop(file);
+        fs::rename(tmp, &self.path)
+    }
+}
+
+fn lock_path(dir: &Path, session: &str) -> PathBuf {
+    dir.join(format!("{}.lock", session))
+}
+
+/// The session that wrote a swap file or holds a lock file.
+fn owner(path: &Path) -> Option<String> {
+    let session = path.file_stem()?.to_str()?.split('-').next()?;
+    (!session.is_empty()).then(|| session.to_string())
+}
+
+fn read_swap(path: &Path) -> Option<RecoveredBuffer> {
+    let data = fs::read_to_string(path).ok()?;
+    let rest = data.strip_prefix(HEADER)?.strip_prefix('\n')?;
+    let (header, content) = rest.split_once("---\n")?;
+    let mut name = String::from("Recovered");
+    let mut file_path = None;
+    for line in header.lines() {
+        if let Some(value) = line.strip_prefix("name: ") {
+            name = value.to_string();
+        } else if let Some(value) = line.strip_prefix("path: ") {
+            file_path = Some(value.to_string()).filter(|p| !p.is_empty());
+        }
+    }
+    Some(RecoveredBuffer {
+        swap_path: path.to_path_buf(),
+        name,
+        file_path,
+        content: content.to_string(),
+    })
+}
+
+/// Buffer id, buffer version and outcome of each swap written by a background write.
+type Written = Vec<(u64, u64, io::Result<()>)>;
+
+/// Periodically writes the swap files for this session's dirty buffers.
+pub struct Journal {
+    swap: Option<SwapDir>,
+    last_run: Instant,
+    /// Buffer id -> buffer version last written to its swap file. The version
+    /// changes on every edit, even ones merged into an open undo group.
+    written: HashMap<u64, u64>,
+    /// Swap writes still running on a worker thread, so a large buffer does
+    /// not stall the UI while it is written.
+    writing: Option<JoinHandle<Written>>,
+}
+
+impl Journal {
+    pub fn new(swap: Option<SwapDir>) -> Self {
+        Self { swap, last_run: Instant::now(), written: HashMap::new(), writing: None }
+    }
+
+    /// Brings the swap files up to date: writes changed dirty buffers in the
+    /// background and removes swaps of buffers that were saved or closed.
+    /// Errors of the previous run's writes are reported here.
+    pub fn run(&mut self, buffers: &[FileBuffer]) -> io::Result<()> {
+        if self.swap.is_none() || self.last_run.elapsed() < JOURNAL_INTERVAL {
+            return Ok(());
+        }
+        if self.writing.as_ref().is_some_and(|writing| !writing.is_finished()) {
+            return Ok(());
+        }
+        self.last_run = Instant::now();
+        let result = self.finish();
+        let Some(swap) = &self.swap else {
+            return result;
+        };
+
+        let mut snapshots = Vec::new();
+        for buffer in buffers {
+            let version = buffer.version();
+            if buffer.is_dirty() {
+                if self.written.get(&buffer.id) != Some(&version) {
+                    snapshots.push((buffer.id, version, swap.snapshot(buffer)));
+                }
+            } else if self.written.remove(&buffer.id).is_some() {
+                swap.remove(buffer.id);
+            }
+        }
+        self.written.retain(|id, _| {
+            let open = buffers.iter().any(|b| b.id == *id);
+            if !open {
+                swap.remove(*id);
+            }
+            open
+        });
+        if !snapshots.is_empty() {
+            self.writing = Some(thread::spawn(move || {
+                snapshots.into_iter().map(|(id, version, snapshot)| (id, version, snapshot.write())).collect()
+            }));
+        }
+        result
+    }
+
+    /// Waits for the background writes, if any, and records which swaps they wrote.
+    fn finish(&mut self) -> io::Result<()> {
+        let Some(writing) = self.writing.take() else {
+            return Ok(());
+        };
+        let written = writing.join().unwrap_or_else(|_| Vec::new());
+        let mut result = Ok(());
+        for (id, version, outcome) in written {
+            match outcome {
+                Ok(()) => {
+                    self.written.insert(id, version);
+                }
+                Err(err) => result = Err(err),
+            }
+        }
+        result
+    }
+
+    /// Removes every swap file written by this session, and the lock files
+    /// that no longer guard any swaps, on a clean exit.
+    pub fn clear(&mut self) {
+        let _ = self.finish();
+        if let Some(swap) = &mut self.swap {
+            for id in self.written.keys() {
+                swap.remove(*id);
+            }
+            swap.release();
+        }
+        self.written.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_swap(name: &str) -> SwapDir {
+        let dir = std::env::temp_dir().join(format!("notepad2-swap-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        SwapDir::at(dir).unwrap()
+    }
+
+    #[test]
+    fn swap_round_trips_and_skips_live_sessions() {
+        let mut swap = temp_swap("roundtrip");
+        let mut buffer = FileBuffer::new("notes", String::new(), None);
+        buffer.insert_text("unsaved\n---\nwork", 0);
+        swap.snapshot(&buffer).write().unwrap();
+
+        // Our own (running) session's files are never offered back.
+        assert!(swap.leftovers().is_empty());
+
+        // Another instance that is still running holds its lock.
+        let live = SwapDir::locked(swap.dir.clone(), "live".into()).unwrap();
+        live.snapshot(&buffer).write().unwrap();
+        assert!(swap.leftovers().is_empty());
+
+        // Pretend our file belongs to a process that has exited. Without its
+        // lock file nothing proves that, so it is not offered yet.
+        let dead = "dead";
+        fs::rename(swap.path_for(buffer.id), swap.dir.join(format!("{}-{}.swap", dead, buffer.id))).unwrap();
+        assert!(swap.leftovers().is_empty());
+        fs::write(lock_path(&swap.dir, dead), "").unwrap();
+        let found = swap.leftovers();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].name, "notes");
+        assert_eq!(found[0].file_path, None);
+        assert_eq!(found[0].content, "unsaved\n---\nwork");
+
+        // An instance starting meanwhile leaves the claimed swaps alone.
+        let mut other = SwapDir::locked(swap.dir.clone(), "other".into()).unwrap();
+        assert!(other.leftovers().is_empty());
+
+        let recovered = found.into_iter().next().unwrap();
+        fs::remove_file(&recovered.swap_path).unwrap();
+        let restored = recovered.into_buffer();
+        assert_eq!(restored.text().to_string(), "unsaved\n---\nwork");
+        assert!(restored.is_dirty());
+
+        // The dead instance's lock goes once its swaps are dealt with; the live
+        // one's stays while it has swaps.
+        swap.release();
+        assert!(!lock_path(&swap.dir, dead).exists());
+        assert!(!lock_path(&swap.dir, &swap.session).exists());
+        assert!(lock_path(&swap.dir, &live.session).exists());
+        let _ = fs::remove_dir_all(&swap.dir);
+    }
+
+    #[test]
+    fn journal_writes_dirty_and_removes_clean() {
+        let swap = temp_swap("journal");
+        let dir = swap.dir.clone();
+        let mut journal = Journal::new(Some(swap));
+        let mut buffers = vec![FileBuffer::new("a", String::new(), None)];
+        buffers[0].insert_text("x", 0);
+
+        journal.last_run = Instant::now() - JOURNAL_INTERVAL;
+        journal.run(&buffers).unwrap();
+        journal.finish().unwrap();
+        let path = journal.swap.as_ref().unwrap().path_for(buffers[0].id);
+        assert!(path.exists());
+
+        buffers[0].undo();
+        journal.last_run = Instant::now() - JOURNAL_INTERVAL;
+        journal.run(&buffers).unwrap();
+        assert!(!path.exists());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn journal_keeps_up_with_edits_merged_into_one_undo_group() {
+        let swap = temp_swap("group");
+        let dir = swap.dir.clone();
+        let mut journal = Journal::new(Some(swap));
+        let mut buffers = vec![FileBuffer::new("a", String::new(), None)];
+        buffers[0].insert_text("w", 0);
+
+        journal.last_run = Instant::now() - JOURNAL_INTERVAL;
+        journal.run(&buffers).unwrap();
+        journal.finish().unwrap();
+        let revision = buffers[0].history.revision();
+
+        // Further keystrokes of the same word extend the open group.
+        for (i, c) in ["o", "r", "d"].into_iter().enumerate() {
+            buffers[0].insert_text(c, i + 1);
+        }
+        assert_eq!(buffers[0].history.revision(), revision);
+
+        journal.last_run = Instant::now() - JOURNAL_INTERVAL;
+        journal.run(&buffers).unwrap();
+        journal.finish().unwrap();
+        let path = journal.swap.as_ref().unwrap().path_for(buffers[0].id);
+        assert_eq!(read_swap(&path).unwrap().content, "word");
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn crashed_session_with_our_process_id_is_offered_and_kept() {
+        let mut swap = temp_swap("reused-pid");
+        // A session that crashed under the process id we now have.
+        let crashed = format!("{}_1", std::process::id());
+        let mut buffer = FileBuffer::new("crashed", String::new(), None);
+        buffer.insert_text("lost work", 0);
+        let old = SwapDir::locked(swap.dir.clone(), crashed.clone()).unwrap();
+        old.snapshot(&buffer).write().unwrap();
+        let old_swap = old.path_for(buffer.id);
+        drop(old);
+
+        let found = swap.leftovers();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].content, "lost work");
+
+        // Our own buffer with the same id writes its swap elsewhere, and a
+        // clean exit leaves the crashed session's swap alone.
+        let mut journal = Journal::new(Some(swap));
+        let mut buffers = vec![buffer.clone()];
+        buffers[0].insert_text("new ", 0);
+        journal.last_run = Instant::now() - JOURNAL_INTERVAL;
+        journal.run(&buffers).unwrap();
+        journal.clear();
+        assert_eq!(read_swap(&old_swap).unwrap().content, "lost work");
+        let _ = fs::remove_dir_all(old_swap.parent().unwrap());
+    }
+}
@@ -0,0 +1,166 @@
+//! Matches gathered from several tabs or files, listed in the results panel.
+
+use std::ops::Range;
+use std::path::PathBuf;
+
+use crate::file_buffer::FileBuffer;
+use crate::search::Matcher;
+
+/// Longest line preview shown in the results panel, in characters.
+const PREVIEW_LEN: usize = 160;
+
+/// One match, located well enough to jump to it later.
+pub struct SearchResult {
+    /// Zero-based line of the match start.
+    pub line: usize,
+    /// Character range of the match in the tab at `version`, or in the file as
+    /// it was on disk when `version` is `None`.
+    pub chars: Range<usize>,
+    pub version: Option<u64>,
+    /// The matched line, trimmed, for showing next to the line number.
+    pub preview: String,
+    /// Byte range of the match within `preview`, if it fits on the line.
+    pub preview_match: Option<Range<usize>>,
+    /// `preview` with the match replaced, while a replace is being previewed.
+    pub replaced: Option<String>,
+}
+
+/// Where a group of results was found.
+#[derive(Clone, PartialEq)]
+pub enum ResultSource {
+    Tab(u64),
+    File(PathBuf),
+}
+
+/// Results of one search, grouped by where they were found.
+pub struct SearchResults {
+    /// What was searched for, e.g. `"foo" in open tabs`.
+    pub title: String,
+    /// The query that found these results. Replacing uses this rather than the
+    /// find bar, which may have been edited since.
+    pub matcher: Matcher,
+    pub groups: Vec<ResultGroup>,
+}
+
+/// The matches in one tab or file.
+pub struct ResultGroup {
+    pub name: String,
+    pub source: ResultSource,
+    pub results: Vec<SearchResult>,
+}
+
+impl SearchResults {
+    /// No results yet, for a search that fills them in as it goes.
+    pub fn new(title: String, matcher: Matcher) -> Self {
+        Self { title, matcher, groups: Vec::new() }
+    }
+
+    /// Searches every buffer, leaving out buffers without a match.
+    pub fn from_buffers(title: String, matcher: &Matcher, buffers: &[FileBuffer]) -> Self {
+        let groups = buffers
+            .iter()
+            .map(|buffer| ResultGroup {
+                name: buffer.name.clone(),
+                source: ResultSource::Tab(buffer.id),
+                results: search_buffer(matcher, buffer, Some(buffer.version())),
+            })
+            .filter(|group| !group.results.is_empty())
+            .collect();
+        Self { title, matcher: matcher.clone(), groups }
+    }
+
+    pub fn len(&self) -> usize {
+        self.groups.iter().map(|group| group.results.len()).sum()
+    }
+
+    /// Fills in each result's `replaced` line. Matches that cross a line break
+    /// or fall outside the preview are left without one.
+    pub fn preview_replace(&mut self, replace: &str) {
+        for result in self.groups.iter_mut().flat_map(|group| &mut group.results) {
+            result.replaced = result.preview_match.clone().and_then(|range| {
+                let replacement = self.matcher.replacement_at(&result.preview, range.clone(), replace)?;
+                Some(format!("{}{}{}", &result.preview[..range.start], replacement, &result.preview[range.end..]))
+            });
+        }
+    }
+
+    pub fn clear_replace_preview(&mut self) {
+        for result in self.groups.iter_mut().flat_map(|group| &mut group.results) {
+            result.replaced = None;
+        }
+    }
+
+    pub fn is_previewing_replace(&self) -> bool {
+        self.groups.iter().flat_map(|group| &group.results).any(|result| result.replaced.is_some())
+    }
+}
+
+/// Every match in `buffer`. `version` is what the results are valid for:
+/// the buffer's own for an open tab, `None` for a file read from disk.
+pub fn search_buffer(matcher: &Matcher, buffer: &FileBuffer, version: Option<u64>) -> Vec<SearchResult> {
+    let mut results = Vec::new();
+    for (line, (offset, text)) in buffer.lines().enumerate() {
+        for range in matcher.find_all(&text) {
+            let chars = buffer.byte_to_char(offset + range.start)..buffer.byte_to_char(offset + range.end);
+            let (preview, preview_match) = preview(&text, range);
+            results.push(SearchResult { line, chars, version, preview, preview_match, replaced: None });
+        }
+    }
+    results
+}
+
+/// Trims and shortens a line for display, moving `found`, a byte range within
+/// `line`, along with it.
+fn preview(line: &str, found: Range<usize>) -> (String, Option<Range<usize>>) {
+    let trimmed = line.trim_start();
+    let offset = line.len() - trimmed.len();
+    let trimmed = trimmed.trim_end();
+    let (text, cut) = match trimmed.char_indices().nth(PREVIEW_LEN) {
+        Some((end, _)) => (format!("{}\u{2026}", &trimmed[..end]), end),
+        None => (trimmed.to_string(), trimmed.len()),
+    };
+    let found = (found.start >= offset && found.end <= offset + cut).then(|| found.start - offset..found.end - offset);
+    (text, found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::SearchOptions;
+
+    #[test]
+    fn lists_matches_per_tab_with_lines_and_previews() {
+        let buffers = vec![
+            FileBuffer::new("a.txt", "first\n  needle one\nneedle two\n".to_string(), None),
+            FileBuffer::new("b.txt", "nothing here".to_string(), None),
+            FileBuffer::new("c.txt", "é needle".to_string(), None),
+        ];
+        let matcher = Matcher::new("needle", SearchOptions::default()).unwrap();
+        let found = SearchResults::from_buffers("needle".to_string(), &matcher, &buffers);
+
+        assert_eq!(found.len(), 3);
+        let names: Vec<&str> = found.groups.iter().map(|g| g.name.as_str()).collect();
+        assert_eq!(names, vec!["a.txt", "c.txt"]);
+
+        assert!(found.groups[0].source == ResultSource::Tab(buffers[0].id));
+        let first = &found.groups[0].results;
+        assert_eq!((first[0].line, first[0].preview.as_str()), (1, "needle one"));
+        assert_eq!(first[0].preview_match, Some(0..6));
+        assert_eq!((first[1].line, first[1].chars.clone()), (2, 19..25));
+        // Character offsets, not bytes.
+        assert_eq!(found.groups[1].results[0].chars, 2..8);
+    }
+
+    #[test]
+    fn previews_each_replacement_on_its_own_line() {
+        let buffers = vec![FileBuffer::new("a.txt", "  x = old(old)\n".to_string(), None)];
+        let matcher = Matcher::new("old", SearchOptions::default()).unwrap();
+        let mut found = SearchResults::from_buffers(String::new(), &matcher, &buffers);
+        found.preview_replace("new");
+        let lines: Vec<_> = found.groups[0].results.iter().map(|r| r.replaced.clone().unwrap()).collect();
+        assert_eq!(lines, vec!["x = new(old)", "x = old(new)"]);
+        assert!(found.is_previewing_replace());
+        found.clear_replace_preview();
+        assert!(!found.is_previewing_replace());
+    }
+}
@@ -0,0 +1,136 @@
+//! Matching for the find bar. Every search runs through a [`Regex`]; plain
+//! queries are escaped first, so the options work the same in both modes.
+
+use std::ops::Range;
+
+use regex::{Regex, RegexBuilder};
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct SearchOptions {
+    /// Treat the query as a regular expression; the replacement may use `$1`, `${name}`.
+    pub regex: bool,
+    pub match_case: bool,
+    pub whole_word: bool,
+}
+
+/// A compiled query.
+#[derive(Clone)]
+pub struct Matcher {
+    regex: Regex,
+    expand_captures: bool,
+}
+
+/// One match, as byte offsets into the searched text, with its replacement.
+pub struct Match {
+    pub range: Range<usize>,
+    pub replacement: String,
+}
+
+impl Matcher {
+    /// Fails with a message fit for showing next to the query when the pattern is invalid.
+    pub fn new(query: &str, options: SearchOptions) -> Result<Self, String> {
+        if query.is_empty() {
+            return Err("Nothing to search for".to_string());
+        }
+        let mut pattern = if options.regex { query.to_string() } else { regex::escape(query) };
+        if options.whole_word {
+            pattern = format!(r"\b(?:{})\b", pattern);
+        }
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(!options.match_case)
+            .multi_line(true)
+            .build()
+            .map_err(|err| match err {
+                regex::Error::Syntax(msg) => msg.lines().last().unwrap_or("invalid pattern").trim().to_string(),
+                regex::Error::CompiledTooBig(_) => "Pattern is too large".to_string(),
+                _ => "Invalid pattern".to_string(),
+            })?;
+        Ok(Self { regex, expand_captures: options.regex })
+    }
+
+    /// Byte ranges of every non-empty match.
+    pub fn find_all(&self, text: &str) -> Vec<Range<usize>> {
+        self.regex.find_iter(text).filter(|m| !m.is_empty()).map(|m| m.range()).collect()
+    }
+
+    /// The replacement for the match at `range`, or `None` if the text there no longer matches.
+    pub fn replacement_at(&self, text: &str, range: Range<usize>, replace: &str) -> Option<String> {
+        let caps = self.regex.captures_at(text, range.start)?;
+        if caps.get(0)?.range() != range {
+            return None;
+        }
+        let mut replacement = String::new();
+        if self.expand_captures {
+            caps.expand(replace, &mut replacement);
+        } else {
+            replacement.push_str(replace);
+        }
+        Some(replacement)
+    }
+
+    /// Every non-empty match with `replace` expanded for it.
+    pub fn replacements(&self, text: &str, replace: &str) -> Vec<Match> {
+        self.regex
+            .captures_iter(text)
+            .filter_map(|caps| {
+                let whole = caps.get(0)?;
+                if whole.is_empty() {
+                    return None;
+                }
+                let mut replacement = String::new();
+                if self.expand_captures {
+                    caps.expand(replace, &mut replacement);
+                } else {
+                    replacement.push_str(replace);
+                }
+                Some(Match { range: whole.range(), replacement })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(query: &str, options: SearchOptions, text: &str) -> Vec<Range<usize>> {
+        Matcher::new(query, options).unwrap().find_all(text)
+    }
+
+    #[test]
+    fn literal_queries_escape_regex_syntax() {
+        let options = SearchOptions::default();
+        assert_eq!(ranges("a.b", options, "axb a.b"), vec![4..7]);
+        assert_eq!(ranges("CAT", options, "cat Cat"), vec![0..3, 4..7]);
+        let case = SearchOptions { match_case: true, ..options };
+        assert_eq!(ranges("Cat", case, "cat Cat"), vec![4..7]);
+    }
+
+    #[test]
+    fn whole_word_skips_partial_matches() {
+        let options = SearchOptions { whole_word: true, ..Default::default() };
+        assert_eq!(ranges("cat", options, "cat concat cats cat."), vec![0..3, 16..19]);
+    }
+
+    #[test]
+    fn regex_replacements_expand_capture_groups() {
+        let options = SearchOptions { regex: true, match_case: true, ..Default::default() };
+        let matcher = Matcher::new(r"(\w+)@(\w+)", options).unwrap();
+        let found = matcher.replacements("me@home, you@work", "$2:$1");
+        let replaced: Vec<&str> = found.iter().map(|m| m.replacement.as_str()).collect();
+        assert_eq!(replaced, vec!["home:me", "work:you"]);
+
+        // Without regex mode, `$1` is just text.
+        let literal = Matcher::new("me", SearchOptions::default()).unwrap();
+        assert_eq!(literal.replacements("me", "$1")[0].replacement, "$1");
+    }
+
+    #[test]
+    fn invalid_patterns_are_errors() {
+        let options = SearchOptions { regex: true, ..Default::default() };
+        assert!(Matcher::new("(unclosed", options).is_err());
+        assert!(Matcher::new("", options).is_err());
+        // The same text is fine as a literal search.
+        assert!(Matcher::new("(unclosed", SearchOptions::default()).is_ok());
+    }
+}
@@ -0,0 +1,156 @@
+//! The open tabs carried over from one run to the next, kept in eframe's app
+//! storage. Settings live in the config file instead.
+//!
+//! Only tabs backed by a file are reopened. Unsaved text, including untitled
+//! tabs, is the recovery journal's job.
+
+use std::path::Path;
+
+use eframe::egui::Vec2;
+use encoding_rs::Encoding;
+use serde::{Deserialize, Serialize};
+
+use crate::encoding::TextEncoding;
+use crate::error::BufferError;
+use crate::file_buffer::FileBuffer;
+use crate::notepad_app::NotepadApp;
+
+pub const STORAGE_KEY: &str = "session";
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Session {
+    pub tabs: Vec<TabSession>,
+    /// Index into `tabs`.
+    pub current_tab: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TabSession {
+    pub name: String,
+    pub file_path: String,
+    pub cursor: usize,
+    pub scroll: [f32; 2],
+    /// Encoding name the file was last read or saved with.
+    #[serde(default)]
+    pub encoding: Option<String>,
+}
+
+impl TabSession {
+    pub fn from_buffer(buffer: &FileBuffer) -> Option<Self> {
+        Some(Self {
+            name: buffer.name.clone(),
+            file_path: buffer.file_path.clone()?,
+            cursor: buffer.cursor,
+            scroll: [buffer.scroll.x, buffer.scroll.y],
+            encoding: Some(buffer.encoding.encoding.name().to_string()),
+        })
+    }
+
+    /// Reads the file again and puts the cursor and scroll position back where they were.
+    pub fn open(&self) -> Result<FileBuffer, BufferError> {
+        let path = Path::new(&self.file_path);
+        // Keep an encoding the user chose by hand, unless the file has since
+        // changed into something it no longer decodes as.
+        let encoding = self
+            .encoding
+            .as_deref()
+            .and_then(|label| Encoding::for_label(label.as_bytes()))
+            .map(|encoding| TextEncoding { encoding, bom: false });
+        let mut buffer = match FileBuffer::open_with(path, encoding) {
+            Err(BufferError::InvalidText(..)) => FileBuffer::open(path)?,
+            result => result?,
+        };
+        buffer.name = self.name.clone();
+        // The file may have shrunk since the last run.
+        let cursor = self.cursor.min(buffer.len_chars());
+        buffer.cursor = cursor;
+        buffer.pending_cursor = Some(cursor);
+        buffer.pending_scroll = Some(Vec2::new(self.scroll[0], self.scroll[1]));
+        Ok(buffer)
+    }
+}
+
+impl Session {
+    pub fn capture(app: &NotepadApp) -> Self {
+        let mut tabs = Vec::new();
+        let mut current_tab = 0;
+        for (i, buffer) in app.buffers.iter().enumerate() {
+            if let Some(tab) = TabSession::from_buffer(buffer) {
+                if i <= app.current_tab {
+                    current_tab = tabs.len();
+                }
+                tabs.push(tab);
+            }
+        }
+        Self { tabs, current_tab }
+    }
+
+    /// Reopens the saved tabs, returning them with the errors for those that could not be read.
+    pub fn open_tabs(&self) -> (Vec<FileBuffer>, Vec<BufferError>) {
+        let mut buffers = Vec::new();
+        let mut errors = Vec::new();
+        for tab in &self.tabs {
+            match tab.open() {
+                Ok(buffer) => buffers.push(buffer),
+                Err(err) => errors.push(err),
+            }
+        }
+        (buffers, errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("notepad2-session-{}-{}", std::process::id(), name));
+        path.display().to_string()
+    }
+
+    #[test]
+    fn tabs_reopen_with_name_and_cursor() {
+        let path = temp_path("reopen");
+        fs::write(&path, "hello world").unwrap();
+        let mut buffer = FileBuffer::new("my notes", "hello world".into(), Some(path.clone()));
+        buffer.cursor = 6;
+        buffer.scroll = Vec2::new(0.0, 42.0);
+
+        let session = Session {
+            tabs: vec![TabSession::from_buffer(&buffer).unwrap()],
+            ..Default::default()
+        };
+        let (buffers, errors) = session.open_tabs();
+        assert!(errors.is_empty());
+        assert_eq!(buffers[0].name, "my notes");
+        assert_eq!(buffers[0].text().to_string(), "hello world");
+        assert_eq!(buffers[0].pending_cursor, Some(6));
+        assert_eq!(buffers[0].pending_scroll, Some(Vec2::new(0.0, 42.0)));
+        assert!(!buffers[0].is_dirty());
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn untitled_tabs_are_skipped_and_missing_files_reported() {
+        let untitled = FileBuffer::new("Untitled", "scratch".into(), None);
+        assert!(TabSession::from_buffer(&untitled).is_none());
+
+        let path = temp_path("shrunk");
+        fs::write(&path, "abc").unwrap();
+        let session = Session {
+            tabs: vec![
+                TabSession { name: "gone".into(), file_path: temp_path("gone"), cursor: 0, scroll: [0.0; 2], encoding: None },
+                TabSession { name: "shrunk".into(), file_path: path.clone(), cursor: 99, scroll: [0.0; 2], encoding: None },
+            ],
+            ..Default::default()
+        };
+        let (buffers, errors) = session.open_tabs();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(&errors[0], BufferError::NotFound(p, _) if *p == temp_path("gone")));
+        assert_eq!(buffers.len(), 1);
+        assert_eq!(buffers[0].pending_cursor, Some(3));
+        let _ = fs::remove_file(path);
+    }
+}
@@ -1,21 +1,342 @@
 //! Theme preference handling for the Rust Notepad app.
+//!
+//! Besides egui's built-in light and dark looks, themes can be loaded from
+//! JSON files in the themes directory. A theme file names a base look and
+//! overrides any of its colors and the syntax colors, e.g.:
+//!
+//! ```json
+//! {
+//!   "name": "Solarized Dark",
+//!   "dark": true,
+//!   "ui": { "editor_background": "#002b36", "text": "#839496" },
+//!   "syntax": { "keyword": "#859900", "string": "#2aa198" }
+//! }
+//! ```
 
-use eframe::egui;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-#[derive(PartialEq)]
+use eframe::egui::{self, Color32};
+use serde::Deserialize;
+
+use crate::config;
+use crate::highlight::TokenKind;
+
+#[derive(Clone, PartialEq)]
 pub enum ThemePreference {
+    /// Follows the OS dark/light setting.
     System,
     Light,
     Dark,
+    /// A theme loaded from the themes directory, by name.
+    Custom(String),
 }
 
 impl ThemePreference {
-    pub fn apply(&self, ctx: &egui::Context) {
-        let visuals = match self {
-            ThemePreference::System => egui::Visuals::default(),
-            ThemePreference::Light => egui::Visuals::light(),
-            ThemePreference::Dark => egui::Visuals::dark(),
+    /// Applies the preference and returns the syntax colors to use with it.
+    /// A custom theme that is no longer installed falls back to the system look.
+    pub fn apply(&self, ctx: &egui::Context, themes: &[Theme]) -> SyntaxColors {
+        let system_dark = ctx.system_theme() != Some(egui::Theme::Light);
+        let (visuals, syntax) = match self {
+            ThemePreference::Light => (egui::Visuals::light(), SyntaxColors::LIGHT),
+            ThemePreference::Dark => (egui::Visuals::dark(), SyntaxColors::DARK),
+            ThemePreference::Custom(name) if let Some(theme) = themes.iter().find(|t| &t.name == name) => {
+                (theme.visuals(), theme.syntax)
+            }
+            ThemePreference::System | ThemePreference::Custom(_) if system_dark => {
+                (egui::Visuals::dark(), SyntaxColors::DARK)
+            }
+            ThemePreference::System | ThemePreference::Custom(_) => (egui::Visuals::light(), SyntaxColors::LIGHT),
         };
         ctx.set_visuals(visuals);
+        syntax
+    }
+}
+
+/// Colors for each kind of highlighted token.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SyntaxColors {
+    pub keyword: Color32,
+    pub type_name: Color32,
+    pub constant: Color32,
+    pub string: Color32,
+    pub number: Color32,
+    pub comment: Color32,
+    pub heading: Color32,
+    pub key: Color32,
+}
+
+impl SyntaxColors {
+    /// The One Dark palette.
+    pub const DARK: Self = Self {
+        keyword: Color32::from_rgb(198, 120, 221),
+        type_name: Color32::from_rgb(229, 192, 123),
+        constant: Color32::from_rgb(209, 154, 102),
+        string: Color32::from_rgb(152, 195, 121),
+        number: Color32::from_rgb(209, 154, 102),
+        comment: Color32::from_rgb(127, 132, 142),
+        heading: Color32::from_rgb(97, 175, 239),
+        key: Color32::from_rgb(224, 108, 117),
+    };
+
+    /// The One Light palette.
+    pub const LIGHT: Self = Self {
+        keyword: Color32::from_rgb(166, 38, 164),
+        type_name: Color32::from_rgb(193, 132, 1),
+        constant: Color32::from_rgb(152, 104, 1),
+        string: Color32::from_rgb(80, 161, 79),
+        number: Color32::from_rgb(152, 104, 1),
+        comment: Color32::from_rgb(160, 161, 167),
+        heading: Color32::from_rgb(64, 120, 242),
+        key: Color32::from_rgb(228, 86, 73),
+    };
+
+    pub fn color(&self, kind: TokenKind) -> Color32 {
+        match kind {
+            TokenKind::Keyword => self.keyword,
+            TokenKind::Type => self.type_name,
+            TokenKind::Constant => self.constant,
+            TokenKind::String => self.string,
+            TokenKind::Number => self.number,
+            TokenKind::Comment => self.comment,
+            TokenKind::Heading => self.heading,
+            TokenKind::Key => self.key,
+        }
+    }
+}
+
+/// A theme read from a file, with its colors checked.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Theme {
+    pub name: String,
+    pub dark: bool,
+    ui: UiColors,
+    pub syntax: SyntaxColors,
+}
+
+/// Overrides for the base look; unset colors keep egui's.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+struct UiColors {
+    editor_background: Option<Color32>,
+    panel_background: Option<Color32>,
+    widget_background: Option<Color32>,
+    faint_background: Option<Color32>,
+    text: Option<Color32>,
+    selection: Option<Color32>,
+    hyperlink: Option<Color32>,
+    warning: Option<Color32>,
+    error: Option<Color32>,
+}
+
+/// The file format; colors are `#rrggbb` or `#rrggbbaa` strings.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ThemeFile {
+    name: String,
+    #[serde(default = "default_dark")]
+    dark: bool,
+    #[serde(default)]
+    ui: UiColorsFile,
+    #[serde(default)]
+    syntax: SyntaxColorsFile,
+}
+
+fn default_dark() -> bool {
+    true
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct UiColorsFile {
+    editor_background: Option<String>,
+    panel_background: Option<String>,
+    widget_background: Option<String>,
+    faint_background: Option<String>,
+    text: Option<String>,
+    selection: Option<String>,
+    hyperlink: Option<String>,
+    warning: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct SyntaxColorsFile {
+    keyword: Option<String>,
+    #[serde(rename = "type")]
+    type_name: Option<String>,
+    constant: Option<String>,
+    string: Option<String>,
+    number: Option<String>,
+    comment: Option<String>,
+    heading: Option<String>,
+    key: Option<String>,
+}
+
+impl Theme {
+    /// Parses a theme file's contents; errors name the offending field.
+    pub fn parse(json: &str) -> Result<Self, String> {
+        let file: ThemeFile = serde_json::from_str(json).map_err(|err| err.to_string())?;
+        let color = |field: &str, value: &Option<String>| -> Result<Option<Color32>, String> {
+            value.as_deref().map(parse_color).transpose().map_err(|err| format!("{}: {}", field, err))
+        };
+        let ui = &file.ui;
+        let ui = UiColors {
+            editor_background: color("ui.editor_background", &ui.editor_background)?,
+            panel_background: color("ui.panel_background", &ui.panel_background)?,
+            widget_background: color("ui.widget_background", &ui.widget_background)?,
+            faint_background: color("ui.faint_background", &ui.faint_background)?,
+            text: color("ui.text", &ui.text)?,
+            selection: color("ui.selection", &ui.selection)?,
+            hyperlink: color("ui.hyperlink", &ui.hyperlink)?,
+            warning: color("ui.warning", &ui.warning)?,
+            error: color("ui.error", &ui.error)?,
+        };
+        let base = if file.dark { SyntaxColors::DARK } else { SyntaxColors::LIGHT };
+        let s = &file.syntax;
+        let syntax = SyntaxColors {
+            keyword: color("syntax.keyword", &s.keyword)?.unwrap_or(base.keyword),
+            type_name: color("syntax.type", &s.type_name)?.unwrap_or(base.type_name),
+            constant: color("syntax.constant", &s.constant)?.unwrap_or(base.constant),
+            string: color("syntax.string", &s.string)?.unwrap_or(base.string),
+            number: color("syntax.number", &s.number)?.unwrap_or(base.number),
+            comment: color("syntax.comment", &s.comment)?.unwrap_or(base.comment),
+            heading: color("syntax.heading", &s.heading)?.unwrap_or(base.heading),
+            key: color("syntax.key", &s.key)?.unwrap_or(base.key),
+        };
+        Ok(Self { name: file.name, dark: file.dark, ui, syntax })
+    }
+
+    pub fn visuals(&self) -> egui::Visuals {
+        let mut visuals = if self.dark { egui::Visuals::dark() } else { egui::Visuals::light() };
+        let ui = &self.ui;
+        if let Some(color) = ui.editor_background {
+            visuals.extreme_bg_color = color;
+        }
+        if let Some(color) = ui.panel_background {
+            visuals.panel_fill = color;
+            visuals.window_fill = color;
+        }
+        if let Some(color) = ui.widget_background {
+            for widget in [&mut visuals.widgets.inactive, &mut visuals.widgets.noninteractive] {
+                widget.bg_fill = color;
+                widget.weak_bg_fill = color;
+            }
+        }
+        if let Some(color) = ui.faint_background {
+            visuals.faint_bg_color = color;
+        }
+        visuals.override_text_color = ui.text;
+        if let Some(color) = ui.selection {
+            visuals.selection.bg_fill = color;
+        }
+        if let Some(color) = ui.hyperlink {
+            visuals.hyperlink_color = color;
+        }
+        if let Some(color) = ui.warning {
+            visuals.warn_fg_color = color;
+        }
+        if let Some(color) = ui.error {
+            visuals.error_fg_color = color;
+        }
+        visuals
+    }
+}
+
+fn parse_color(text: &str) -> Result<Color32, String> {
+    let hex = text.strip_prefix('#').filter(|hex| matches!(hex.len(), 6 | 8) && hex.is_ascii());
+    let Some(hex) = hex else {
+        return Err(format!("\"{}\" is not a #rrggbb or #rrggbbaa color", text));
+    };
+    let byte = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16);
+    let channels = (0..hex.len()).step_by(2).map(byte).collect::<Result<Vec<_>, _>>();
+    match channels.as_deref() {
+        Ok([r, g, b]) => Ok(Color32::from_rgb(*r, *g, *b)),
+        Ok([r, g, b, a]) => Ok(Color32::from_rgba_unmultiplied(*r, *g, *b, *a)),
+        _ => Err(format!("\"{}\" is not a #rrggbb or #rrggbbaa color", text)),
+    }
+}
+
+/// Where theme files live: `themes` in the app's config directory.
+pub fn themes_dir() -> Option<PathBuf> {
+    Some(config::dir()?.join("themes"))
+}
+
+/// Every `*.json` theme in `dir`, sorted by name, plus a message for each file
+/// that could not be read. A missing directory just means no themes.
+pub fn load_themes(dir: &Path) -> (Vec<Theme>, Vec<String>) {
+    let mut themes: Vec<Theme> = Vec::new();
+    let mut errors = Vec::new();
+    let Ok(entries) = fs::read_dir(dir) else {
+        return (themes, errors);
+    };
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+        match fs::read_to_string(&path).map_err(|err| err.to_string()).and_then(|json| Theme::parse(&json)) {
+            Ok(theme) if themes.iter().any(|t| t.name == theme.name) => {
+                errors.push(format!("{}: another theme is already named \"{}\"", path.display(), theme.name));
+            }
+            Ok(theme) => themes.push(theme),
+            Err(err) => errors.push(format!("{}: {}", path.display(), err)),
+        }
+    }
+    themes.sort_by(|a, b| a.name.cmp(&b.name));
+    (themes, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_overrides_and_falls_back_to_the_base_palette() {
+        let theme = Theme::parse(
+            r##"{
+                "name": "Paper",
+                "dark": false,
+                "ui": { "editor_background": "#fdf6e3", "selection": "#268bd240" },
+                "syntax": { "keyword": "#859900", "type": "#B58900" }
+            }"##,
+        )
+        .unwrap();
+        assert_eq!(theme.name, "Paper");
+        assert_eq!(theme.syntax.keyword, Color32::from_rgb(0x85, 0x99, 0x00));
+        assert_eq!(theme.syntax.type_name, Color32::from_rgb(0xb5, 0x89, 0x00));
+        assert_eq!(theme.syntax.string, SyntaxColors::LIGHT.string);
+
+        let visuals = theme.visuals();
+        assert!(!visuals.dark_mode);
+        assert_eq!(visuals.extreme_bg_color, Color32::from_rgb(0xfd, 0xf6, 0xe3));
+        assert_eq!(visuals.selection.bg_fill, Color32::from_rgba_unmultiplied(0x26, 0x8b, 0xd2, 0x40));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn reports_bad_colors_and_unknown_fields() {
+        let err = Theme::parse(r#"{ "name": "x", "syntax": { "keyword": "green" } }"#).unwrap_err();
+        assert!(err.starts_with("syntax.keyword:"), "{}", err);
+        assert!(Theme::parse(r##"{ "name": "x", "ui": { "txt": "#ffffff" } }"##).is_err());
+        assert!(Theme::parse(r#"{ "dark": true }"#).is_err());
+        assert!(parse_color("#12345").is_err());
+        assert!(parse_color("#zz0000").is_err());
+    }
+
+    #[test]
+    fn loads_every_json_file_in_the_directory() {
+        let dir = std::env::temp_dir().join(format!("notepad2-themes-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("b.json"), r#"{ "name": "Beta" }"#).unwrap();
+        fs::write(dir.join("a.json"), r#"{ "name": "Alpha", "dark": false }"#).unwrap();
+        fs::write(dir.join("broken.json"), "{").unwrap();
+        fs::write(dir.join("notes.txt"), "not a theme").unwrap();
+
+        let (themes, errors) = load_themes(&dir);
+        let names: Vec<&str> = themes.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["Alpha", "Beta"]);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("broken.json"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
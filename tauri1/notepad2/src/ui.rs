@@ -1,9 +1,18 @@
-use crate::notepad_app::NotepadApp;
-use crate::notepad_app::ThemePreference;
+use crate::atomic_write::BackupMode;
+use crate::autosave::AutosaveMode;
+use crate::encoding::{self, TextEncoding};
+use crate::fonts;
+use crate::goto::GotoDialog;
+use crate::keymap::Action;
+use crate::language::Language;
+use crate::line_ending::LineEnding;
+use crate::search::Matcher;
+use crate::notepad_app::{CloseRequest, NotepadApp};
+use crate::theme::ThemePreference;
 use eframe::egui;
 use std::fs;
-use egui::{Context, TextEdit, Key};
-use crate::file_buffer::FileBuffer;
+use std::time::Duration;
+use egui::{Context, Key};
 
 
 
@@ -14,166 +23,738 @@ pub fn show_menu_bar(app: &mut NotepadApp, ctx: &egui::Context) {
                 if ui.button("Open").clicked() {
                     ui.close_menu();
                     if let Some(path) = rfd::FileDialog::new().pick_file() {
-                        if let Ok(contents) = fs::read_to_string(&path) {
-                            let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
-                            app.buffers.push(crate::file_buffer::FileBuffer::new(&name, contents, Some(path.display().to_string())));
-                            app.current_tab = app.buffers.len() - 1;
-                            app.status = format!("Opened: {}", path.display());
+                        match crate::file_buffer::FileBuffer::open(&path) {
+                            Ok(buffer) => {
+                                app.buffers.push(buffer);
+                                app.current_tab = app.buffers.len() - 1;
+                                app.status.set(format!("Opened: {}", path.display()));
+                            }
+                            Err(err) => app.report_error(format!("Could not open {}", err)),
                         }
                     }
                 }
                 if ui.button("Save").clicked() {
-                    if let Some(buffer) = app.buffers.get_mut(app.current_tab) {
-                        let save_path = buffer.file_path.clone()
-                            .or_else(|| rfd::FileDialog::new().save_file().map(|p| p.display().to_string()));
-                        if let Some(path) = save_path {
-                            if fs::write(&path, &buffer.content).is_ok() {
-                                buffer.file_path = Some(path.clone());
-                                app.status = format!("Saved: {}", path);
+                    app.save_tab(app.current_tab);
+                    ui.close_menu();
+                }
+                let current = app.buffers.get(app.current_tab);
+                let current_encoding = current.map(|b| b.encoding);
+                // Reopening throws away edits and undo history, so it needs a clean tab on disk.
+                let can_reopen = current.is_some_and(|b| b.file_path.is_some() && !b.is_dirty());
+                ui.add_enabled_ui(can_reopen, |ui| {
+                    ui.menu_button("Reopen with Encoding", |ui| {
+                        // The BOM is picked up from the file, so UTF-8 needs only one entry.
+                        for &encoding in encoding::CHOICES.iter().filter(|&&e| e != TextEncoding::UTF8_BOM) {
+                            let selected = current_encoding.is_some_and(|e| e.encoding == encoding.encoding);
+                            if ui.selectable_label(selected, encoding.encoding.name()).clicked() {
+                                app.reopen_tab_with_encoding(app.current_tab, encoding);
+                                ui.close_menu();
                             }
                         }
+                    });
+                });
+                ui.menu_button("Save with Encoding", |ui| {
+                    for &encoding in encoding::CHOICES {
+                        if ui.selectable_label(current_encoding == Some(encoding), encoding.to_string()).clicked() {
+                            app.save_tab_with_encoding(app.current_tab, encoding);
+                            ui.close_menu();
+                        }
                     }
-                    ui.close_menu();
-                }
+                });
                 if ui.button("New Tab").clicked() {
                     app.buffers.push(crate::file_buffer::FileBuffer::new("Untitled", String::new(), None));
                     app.current_tab = app.buffers.len() - 1;
                     ui.close_menu();
                 }
                 if ui.button("Exit").clicked() {
-                    std::process::exit(0);
+                    ui.close_menu();
+                    app.request_exit(ctx);
+                }
+            });
+
+            ui.menu_button("Edit", |ui| {
+                if let Some(buffer) = app.buffers.get_mut(app.current_tab) {
+                    if ui.add_enabled(buffer.history.can_undo(), egui::Button::new("Undo")).clicked() {
+                        buffer.undo();
+                        ui.close_menu();
+                    }
+                    if ui.add_enabled(buffer.history.can_redo(), egui::Button::new("Redo")).clicked() {
+                        buffer.redo();
+                        ui.close_menu();
+                    }
+                    ui.separator();
+                    if ui.button("Find and Replace").clicked() {
+                        app.find_bar.open();
+                        ui.close_menu();
+                    }
+                    ui.menu_button("Convert Line Endings", |ui| {
+                        for style in LineEnding::ALL {
+                            if ui.selectable_label(buffer.line_endings.style == style, style.to_string()).clicked() {
+                                buffer.set_line_ending(style);
+                                ui.close_menu();
+                            }
+                        }
+                    });
+                }
+                if ui.add_enabled(!app.buffers.is_empty(), egui::Button::new("Go to Line\u{2026}")).clicked() {
+                    app.goto_dialog = Some(GotoDialog::default());
+                    ui.close_menu();
+                }
+                if ui.button("Find in Files\u{2026}").clicked() {
+                    if app.find_in_files_root.is_empty() {
+                        // Start from the current file's folder.
+                        let dir = app.buffers.get(app.current_tab).and_then(|b| {
+                            std::path::Path::new(b.file_path.as_deref()?).parent().map(|p| p.display().to_string())
+                        });
+                        app.find_in_files_root = dir.unwrap_or_default();
+                    }
+                    app.show_find_in_files = true;
+                    ui.close_menu();
+                }
+                if ui.button("Find in Open Tabs").clicked() {
+                    app.find_bar.open();
+                    if !app.find_query.is_empty() {
+                        app.find_in_open_tabs();
+                    }
+                    ui.close_menu();
                 }
             });
 
             ui.menu_button("Settings", |ui| {
-                ui.label("Font Scale");
-                ui.add(egui::Slider::new(&mut app.font_scale, 0.5..=2.5).text("x"));
-                ui.checkbox(&mut app.wrap_text, "Wrap lines");
+                ui.label("UI Scale");
+                app.settings_changed |= ui.add(egui::Slider::new(&mut app.font_scale, 0.5..=2.5).text("x")).changed();
+                app.settings_changed |= ui.checkbox(&mut app.wrap_text, "Wrap lines").changed();
+                app.settings_changed |= ui.checkbox(&mut app.show_line_numbers, "Line numbers").changed();
+                app.settings_changed |= ui.checkbox(&mut app.restore_tabs, "Reopen tabs on startup").changed();
+                ui.separator();
+                ui.label("Editor Font");
+                let mut size = app.font_size;
+                let slider = egui::Slider::new(&mut size, fonts::MIN_FONT_SIZE..=fonts::MAX_FONT_SIZE)
+                    .step_by(0.5)
+                    .text("pt");
+                if ui.add(slider).changed() {
+                    app.set_font_size(size);
+                }
+                for (family, label) in [
+                    (egui::FontFamily::Monospace, "Built-in monospace"),
+                    (egui::FontFamily::Proportional, "Built-in proportional"),
+                ] {
+                    let selected = app.font_files.editor.is_none() && app.font_family == family;
+                    if ui.radio(selected, label).clicked() {
+                        app.font_family = family;
+                        app.font_files.editor = None;
+                        app.settings_changed = true;
+                    }
+                }
+                if let Some(path) = &app.font_files.editor {
+                    let name = path.file_name().map_or_else(|| path.display().to_string(), |n| n.to_string_lossy().into());
+                    let _ = ui.radio(true, name).on_hover_text(path.display().to_string());
+                }
+                if ui.button("Choose Font File\u{2026}").clicked() {
+                    ui.close_menu();
+                    if let Some(path) = pick_font_file() {
+                        app.font_files.editor = Some(path);
+                        app.settings_changed = true;
+                    }
+                }
+                ui.horizontal(|ui| {
+                    ui.label(format!("{} fallback font(s)", app.font_files.fallbacks.len()))
+                        .on_hover_text("Used for characters the editor font lacks, such as CJK");
+                    if ui.button("Add\u{2026}").clicked() {
+                        ui.close_menu();
+                        if let Some(path) = pick_font_file() {
+                            app.font_files.fallbacks.push(path);
+                            app.settings_changed = true;
+                        }
+                    }
+                    if !app.font_files.fallbacks.is_empty() && ui.button("Clear").clicked() {
+                        app.font_files.fallbacks.clear();
+                        app.settings_changed = true;
+                    }
+                });
+                ui.separator();
+                app.settings_changed |= ui.checkbox(&mut app.soft_tabs, "Insert spaces for Tab").changed();
+                app.settings_changed |=
+                    ui.add_enabled(app.soft_tabs, egui::Slider::new(&mut app.tab_width, 1..=16).text("spaces")).changed();
+                if let Some(path) = app.config.as_ref().map(|file| file.path.clone())
+                    && ui.button("Open Settings File").clicked()
+                {
+                    ui.close_menu();
+                    app.open_config_file(path);
+                }
+                ui.separator();
+                ui.label("Autosave");
+                app.settings_changed |= ui.radio_value(&mut app.autosave.mode, AutosaveMode::Off, "Off").changed();
+                app.settings_changed |= ui.radio_value(&mut app.autosave.mode, AutosaveMode::AfterIdle, "After idle").changed();
+                app.settings_changed |= ui.radio_value(&mut app.autosave.mode, AutosaveMode::Interval, "Every interval").changed();
+                let mut secs = app.autosave.delay.as_secs();
+                if ui
+                    .add_enabled(
+                        app.autosave.mode != AutosaveMode::Off,
+                        egui::Slider::new(&mut secs, 1..=300).text("seconds"),
+                    )
+                    .changed()
+                {
+                    app.autosave.delay = Duration::from_secs(secs);
+                    app.settings_changed = true;
+                }
+                ui.separator();
+                ui.label("Backup on save");
+                app.settings_changed |= ui.radio_value(&mut app.backup, BackupMode::None, "None").changed();
+                app.settings_changed |= ui.radio_value(&mut app.backup, BackupMode::Tilde, "file~").changed();
+                app.settings_changed |= ui.radio_value(&mut app.backup, BackupMode::Timestamped, "Timestamped copy").changed();
                 ui.separator();
                 ui.label("Theme");
-                ui.radio_value(&mut app.theme_pref, ThemePreference::System, "System");
-                ui.radio_value(&mut app.theme_pref, ThemePreference::Light, "Light");
-                ui.radio_value(&mut app.theme_pref, ThemePreference::Dark, "Dark");
+                let mut choices = vec![
+                    (ThemePreference::System, "System".to_string()),
+                    (ThemePreference::Light, "Light".to_string()),
+                    (ThemePreference::Dark, "Dark".to_string()),
+                ];
+                choices.extend(app.themes.iter().map(|t| (ThemePreference::Custom(t.name.clone()), t.name.clone())));
+                for (choice, label) in choices {
+                    let response = ui.radio(app.theme_pref == choice, label);
+                    if response.clicked() {
+                        app.theme_pref = choice;
+                        app.settings_changed = true;
+                    } else if response.hovered() {
+                        // Previewed on the next frame, so keep frames coming while hovered.
+                        app.theme_preview = Some(choice);
+                        ctx.request_repaint();
+                    }
+                }
+                if let ThemePreference::Custom(name) = &app.theme_pref
+                    && !app.themes.iter().any(|t| &t.name == name)
+                {
+                    ui.weak(format!("\"{}\" is not installed", name));
+                }
+                if ui.button("Reload themes").clicked() {
+                    app.reload_themes();
+                    app.status.set(format!("Loaded {} theme(s)", app.themes.len()));
+                }
             });
         });
     });
 }
 
-pub fn show_status_bar(app: &NotepadApp, ctx: &egui::Context) {
+fn pick_font_file() -> Option<std::path::PathBuf> {
+    rfd::FileDialog::new().add_filter("Fonts", &["ttf", "otf", "ttc"]).pick_file()
+}
+
+pub fn show_status_bar(app: &mut NotepadApp, ctx: &egui::Context) {
     egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
-        ui.label(&app.status);
+        ui.horizontal(|ui| {
+            if let Some((message, left)) = app.status.current() {
+                ui.label(message);
+                ctx.request_repaint_after(left);
+            }
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                if !app.error_log.is_empty() {
+                    let label = format!("\u{26A0} {} error(s)", app.error_log.len());
+                    if ui.selectable_label(app.show_error_log, label).clicked() {
+                        app.show_error_log = !app.show_error_log;
+                    }
+                }
+                let Some(buffer) = app.buffers.get_mut(app.current_tab) else {
+                    return;
+                };
+                ui.menu_button(buffer.language().to_string(), |ui| {
+                    if ui.radio(buffer.language_override.is_none(), "Detect from file").clicked() {
+                        buffer.language_override = None;
+                        ui.close_menu();
+                    }
+                    ui.separator();
+                    for language in Language::ALL {
+                        if ui.radio(buffer.language_override == Some(language), language.to_string()).clicked() {
+                            buffer.language_override = Some(language);
+                            ui.close_menu();
+                        }
+                    }
+                })
+                .response
+                .on_hover_text("Language mode");
+                ui.separator();
+                ui.label(buffer.encoding.to_string());
+                ui.separator();
+                ui.label(buffer.line_endings.to_string());
+                ui.separator();
+                ui.label(format!("{} lines, {} words, {} chars", buffer.len_lines(), buffer.word_count(), buffer.len_chars()));
+                ui.separator();
+                let selected = buffer.selection.len();
+                if selected > 0 {
+                    let lines = buffer.char_to_line(buffer.selection.end) - buffer.char_to_line(buffer.selection.start) + 1;
+                    ui.label(format!("{} selected ({} line(s))", selected, lines));
+                    ui.separator();
+                }
+                let line = buffer.char_to_line(buffer.cursor);
+                let column = buffer.cursor - buffer.line_to_char(line);
+                ui.label(format!("Ln {}, Col {}", line + 1, column + 1));
+                ui.separator();
+                if buffer.is_dirty() {
+                    ui.label("Modified");
+                } else if buffer.file_path.is_some() {
+                    ui.label("Saved");
+                } else {
+                    ui.label("New");
+                }
+            });
+        });
     });
 }
 
-pub fn show_find_replace(app: &mut NotepadApp, ctx: &egui::Context) {
-    if app.show_find {
-        egui::TopBottomPanel::top("find_bar").show(ctx, |ui| {
-            ui.horizontal(|ui| {
-                ui.label("Find:");
-                ui.text_edit_singleline(&mut app.find_query);
-                ui.label("Replace:");
-                ui.text_edit_singleline(&mut app.replace_query);
-                if ui.button("Replace All").clicked() {
-                    if let Some(buffer) = app.buffers.get_mut(app.current_tab) {
-                        buffer.content = buffer.content.replace(&app.find_query, &app.replace_query);
+/// Failed opens and saves from this session, newest at the bottom.
+pub fn show_error_log(app: &mut NotepadApp, ctx: &egui::Context) {
+    if !app.show_error_log {
+        return;
+    }
+    egui::TopBottomPanel::bottom("error_log").resizable(true).show(ctx, |ui| {
+        ui.horizontal(|ui| {
+            ui.strong("Errors");
+            if ui.button("Clear").clicked() {
+                app.error_log.clear();
+                app.show_error_log = false;
+            }
+            if ui.button("Close").clicked() {
+                app.show_error_log = false;
+            }
+        });
+        egui::ScrollArea::vertical().stick_to_bottom(true).show(ui, |ui| {
+            for message in &app.error_log {
+                ui.label(message);
+            }
+        });
+    });
+}
+
+/// Matches from a multi-tab search; clicking one jumps to it.
+pub fn show_search_results(app: &mut NotepadApp, ctx: &egui::Context) {
+    let Some(results) = &mut app.search_results else {
+        return;
+    };
+    enum Action {
+        Open(usize, usize),
+        Close,
+        Cancel,
+        Preview,
+        Replace,
+    }
+    let mut action = None;
+    egui::TopBottomPanel::bottom("search_results").resizable(true).show(ctx, |ui| {
+        ui.horizontal(|ui| {
+            ui.strong(format!("{} result(s) for {}", results.len(), results.title));
+            match &app.file_search {
+                Some(search) if search.running => {
+                    ui.spinner();
+                    ui.label(format!("{} file(s) searched", search.files_searched));
+                    if ui.button("Cancel").clicked() {
+                        action = Some(Action::Cancel);
                     }
                 }
-                if ui.button("Close").clicked() {
-                    app.show_find = false;
+                Some(search) => {
+                    if search.files_skipped > 0 {
+                        ui.label(format!("{} binary or unreadable file(s) skipped", search.files_skipped))
+                            .on_hover_text("Files over 16 MiB are skipped too");
+                    }
+                    if results.is_previewing_replace() {
+                        let files = results.groups.len();
+                        if ui.button(format!("Replace in {} File(s)", files)).clicked() {
+                            action = Some(Action::Replace);
+                        }
+                        if ui.button("Cancel Replace").clicked() {
+                            results.clear_replace_preview();
+                        }
+                    } else if !results.groups.is_empty()
+                        && ui.button("Replace in Files\u{2026}").on_hover_text("Preview replacing with the Replace text").clicked()
+                    {
+                        action = Some(Action::Preview);
+                    }
                 }
-            });
+                None => {}
+            }
+            if ui.button("Close").clicked() {
+                action = Some(Action::Close);
+            }
+        });
+        egui::ScrollArea::vertical().auto_shrink([false, true]).show(ui, |ui| {
+            for (g, group) in results.groups.iter().enumerate() {
+                egui::CollapsingHeader::new(format!("{} ({})", group.name, group.results.len()))
+                    .id_salt(("search_result_group", g))
+                    .default_open(true)
+                    .show(ui, |ui| {
+                        for (i, result) in group.results.iter().enumerate() {
+                            let row = match &result.replaced {
+                                Some(replaced) => format!("{:>6}: - {}\n        + {}", result.line + 1, result.preview, replaced),
+                                None => format!("{:>6}: {}", result.line + 1, result.preview),
+                            };
+                            if ui.selectable_label(false, egui::RichText::new(row).monospace()).clicked() {
+                                action = Some(Action::Open(g, i));
+                            }
+                        }
+                    });
+            }
         });
+    });
+    match action {
+        Some(Action::Open(group, index)) => app.open_search_result(group, index),
+        Some(Action::Close) => {
+            app.search_results = None;
+            app.file_search = None;
+        }
+        Some(Action::Cancel) => {
+            if let Some(search) = &mut app.file_search {
+                search.cancel();
+            }
+            app.status.set("Search cancelled".to_string());
+        }
+        Some(Action::Preview) => app.preview_replace_in_files(),
+        Some(Action::Replace) => app.replace_in_files(),
+        None => {}
     }
 }
 
+/// Folder picker and options for Find in Files.
+pub fn show_find_in_files(app: &mut NotepadApp, ctx: &egui::Context) {
+    if !app.show_find_in_files {
+        return;
+    }
+    let mut open = true;
+    let mut search = false;
+    egui::Window::new("Find in Files").open(&mut open).collapsible(false).resizable(false).show(ctx, |ui| {
+        egui::Grid::new("find_in_files_grid").num_columns(2).show(ui, |ui| {
+            ui.label("Find:");
+            let query = ui.text_edit_singleline(&mut app.find_query);
+            search |= query.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter));
+            ui.end_row();
 
+            ui.label("Replace:");
+            ui.text_edit_singleline(&mut app.replace_query);
+            ui.end_row();
 
+            ui.label("Folder:");
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut app.find_in_files_root);
+                if ui.button("Browse\u{2026}").clicked()
+                    && let Some(dir) = rfd::FileDialog::new().set_directory(&app.find_in_files_root).pick_folder()
+                {
+                    app.find_in_files_root = dir.display().to_string();
+                }
+            });
+            ui.end_row();
+        });
+        ui.horizontal(|ui| {
+            let options = &mut app.find_options;
+            ui.toggle_value(&mut options.match_case, "Aa").on_hover_text("Match case");
+            ui.toggle_value(&mut options.whole_word, "\u{2423}W\u{2423}").on_hover_text("Whole word");
+            ui.toggle_value(&mut options.regex, ".*").on_hover_text("Regular expression");
+        });
+        ui.label("Files ignored by .gitignore and binary files are skipped.");
+        if let Some(err) = &app.find_error {
+            ui.colored_label(ui.visuals().error_fg_color, err);
+        }
+        search |= ui.button("Search").clicked();
+    });
+    if search {
+        app.find_error = None;
+        app.find_searched = None;
+        let root = std::path::PathBuf::from(app.find_in_files_root.trim());
+        app.find_in_files(root, ctx);
+    }
+    app.show_find_in_files = open;
+}
 
-pub fn show_tabs_and_editor(ctx: &Context, app: &mut NotepadApp) {
-    egui::CentralPanel::default().show(ctx, |ui| {
-        // Draw tab headers
+/// Asks for `line`, `line:column` or `+offset` and jumps there.
+pub fn show_goto_dialog(app: &mut NotepadApp, ctx: &egui::Context) {
+    let Some(dialog) = &mut app.goto_dialog else {
+        return;
+    };
+    let lines = app.buffers.get(app.current_tab).map_or(0, |b| b.len_lines());
+    let mut go = false;
+    let mut cancel = false;
+    let modal = egui::Modal::new(egui::Id::new("goto_dialog")).show(ctx, |ui| {
+        ui.heading("Go to Line");
+        ui.label(format!("Line (1\u{2013}{}), line:column, or +/- lines from the cursor", lines));
+        let field = ui.text_edit_singleline(&mut dialog.input);
+        if !dialog.focused {
+            field.request_focus();
+            dialog.focused = true;
+        }
+        if field.changed() {
+            dialog.error = None;
+        }
+        if field.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)) {
+            // Keep the field focused in case the input turns out to be invalid.
+            go = true;
+            field.request_focus();
+        }
+        if let Some(err) = &dialog.error {
+            ui.colored_label(ui.visuals().error_fg_color, err);
+        }
         ui.horizontal(|ui| {
-            for (index, buffer) in app.buffers.iter().enumerate() {
-                let tab_title = buffer.name.as_str();
+            go |= ui.button("Go").clicked();
+            cancel |= ui.button("Cancel").clicked();
+        });
+    });
+    if modal.should_close() || cancel {
+        app.goto_dialog = None;
+        app.focus_editor = true;
+    } else if go {
+        app.goto();
+    }
+}
+
+/// Save / Discard / Cancel prompt for closing a modified tab or exiting with unsaved work.
+pub fn show_close_dialog(app: &mut NotepadApp, ctx: &egui::Context) {
+    let Some(request) = app.pending_close else {
+        return;
+    };
 
-                if ui.selectable_label(app.current_tab == index, tab_title).clicked() {
-                    app.current_tab = index;
+    let mut choice = None;
+    let modal = egui::Modal::new(egui::Id::new("close_dialog")).show(ctx, |ui| {
+        ui.heading("Unsaved changes");
+        match request {
+            CloseRequest::Tab(i) => {
+                let name = app.buffers.get(i).map_or("", |b| b.name.as_str());
+                ui.label(format!("Save changes to {} before closing?", name));
+            }
+            CloseRequest::Exit => {
+                ui.label("These tabs have unsaved changes:");
+                for buffer in app.buffers.iter().filter(|b| b.is_dirty()) {
+                    ui.label(format!("  \u{2022} {}", buffer.name));
                 }
             }
-        });
-
+        }
         ui.separator();
+        ui.horizontal(|ui| {
+            let save_label = if request == CloseRequest::Exit { "Save All" } else { "Save" };
+            if ui.button(save_label).clicked() {
+                choice = Some(CloseChoice::Save);
+            }
+            if ui.button("Discard").clicked() {
+                choice = Some(CloseChoice::Discard);
+            }
+            if ui.button("Cancel").clicked() {
+                choice = Some(CloseChoice::Cancel);
+            }
+        });
+    });
+    if modal.should_close() && choice.is_none() {
+        choice = Some(CloseChoice::Cancel);
+    }
 
-        // Draw the text editor for the selected tab
-        if let Some(buffer) = app.buffers.get_mut(app.current_tab) {
-            ui.add_sized(
-                ui.available_size(), // fills remaining space
-                TextEdit::multiline(&mut buffer.content)
-                    .font(egui::TextStyle::Monospace)
-                    .desired_rows(20)
-                    .lock_focus(true),
-            );
+    let Some(choice) = choice else {
+        return;
+    };
+    app.pending_close = None;
+    match (request, choice) {
+        (_, CloseChoice::Cancel) => {}
+        (CloseRequest::Tab(i), CloseChoice::Save) => {
+            if app.save_tab(i) {
+                app.close_tab(i);
+            }
         }
-    });
+        (CloseRequest::Tab(i), CloseChoice::Discard) => app.close_tab(i),
+        (CloseRequest::Exit, CloseChoice::Save) => {
+            let dirty: Vec<usize> = (0..app.buffers.len()).filter(|&i| app.buffers[i].is_dirty()).collect();
+            // Stop at the first tab that fails or whose Save As is cancelled.
+            if dirty.into_iter().all(|i| app.save_tab(i)) {
+                app.request_exit(ctx);
+            }
+        }
+        (CloseRequest::Exit, CloseChoice::Discard) => {
+            app.allow_close = true;
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+        }
+    }
 }
 
-pub fn handle_keyboard_shortcuts(ctx: &Context, app: &mut NotepadApp) {
-    let input = ctx.input(|i| i.clone());
+/// Offers swap files left by a crashed session back as tabs.
+pub fn show_recovery_dialog(app: &mut NotepadApp, ctx: &egui::Context) {
+    if app.recovered.is_empty() || app.pending_close.is_some() {
+        return;
+    }
 
-    if input.modifiers.ctrl && input.key_pressed(Key::S) {
-        if let Some(current) = app.buffers.get_mut(app.current_tab) {
-            let _ = current.save(); // Save current tab
+    let mut restore = None;
+    egui::Modal::new(egui::Id::new("recovery_dialog")).show(ctx, |ui| {
+        ui.heading("Recover unsaved work");
+        ui.label("Notepad did not shut down cleanly. These buffers had unsaved changes:");
+        for recovered in &app.recovered {
+            let location = recovered.file_path.as_deref().unwrap_or("untitled");
+            ui.label(format!("  \u{2022} {} ({})", recovered.name, location));
         }
-    }
+        ui.separator();
+        ui.horizontal(|ui| {
+            if ui.button("Restore").clicked() {
+                restore = Some(true);
+            }
+            if ui.button("Discard").clicked() {
+                restore = Some(false);
+            }
+        });
+    });
 
-    if input.modifiers.ctrl && input.key_pressed(Key::Z) {
-        if let Some(current) = app.buffers.get_mut(app.current_tab) {
-            current.undo();
+    let Some(restore) = restore else {
+        return;
+    };
+    let recovered = std::mem::take(&mut app.recovered);
+    let count = recovered.len();
+    let mut errors = Vec::new();
+    for item in recovered {
+        if let Err(err) = fs::remove_file(&item.swap_path) {
+            errors.push(format!("Could not remove swap file {}: {}", item.swap_path.display(), err));
+        }
+        if restore {
+            // Replace the blank tab a fresh start opens with.
+            if app.buffers.len() == 1 && app.buffers[0].file_path.is_none() && app.buffers[0].len_chars() == 0 {
+                app.buffers.clear();
+            }
+            // The restored session may already have this file open, unmodified.
+            let reopened = item.file_path.as_ref().and_then(|path| {
+                app.buffers
+                    .iter()
+                    .position(|b| b.file_path.as_ref() == Some(path) && !b.is_dirty())
+            });
+            match reopened {
+                Some(i) => app.buffers[i] = item.into_buffer(),
+                None => app.buffers.push(item.into_buffer()),
+            }
         }
     }
+    if restore {
+        app.current_tab = app.buffers.len() - 1;
+        app.status.set(format!("Restored {} unsaved buffer(s)", count));
+    }
+    // After the summary, so a swap that will be offered again says why.
+    for err in errors {
+        app.report_error(err);
+    }
+}
 
-    if input.modifiers.ctrl && input.key_pressed(Key::Y) {
-        if let Some(current) = app.buffers.get_mut(app.current_tab) {
-            current.redo();
-        }
+#[derive(Clone, Copy)]
+enum CloseChoice {
+    Save,
+    Discard,
+    Cancel,
+}
+
+pub fn show_find_replace(app: &mut NotepadApp, ctx: &egui::Context) {
+    if app.find_bar.is_open() {
+        egui::TopBottomPanel::top("find_bar").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Find:");
+                let query = ui.text_edit_singleline(&mut app.find_query);
+                if app.find_bar.take_query_focus() {
+                    query.request_focus();
+                }
+                let mut changed = query.changed();
+                // Enter steps through matches without leaving the field.
+                let mut step = None;
+                if query.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)) {
+                    step = Some(!ui.input(|i| i.modifiers.shift));
+                    query.request_focus();
+                }
+                let options = &mut app.find_options;
+                changed |= ui.toggle_value(&mut options.match_case, "Aa").on_hover_text("Match case").changed();
+                changed |= ui.toggle_value(&mut options.whole_word, "\u{2423}W\u{2423}").on_hover_text("Whole word").changed();
+                changed |= ui
+                    .toggle_value(&mut options.regex, ".*")
+                    .on_hover_text("Regular expression; use $1 or ${name} in the replacement")
+                    .changed();
+                if changed {
+                    app.find_error = if app.find_query.is_empty() {
+                        None
+                    } else {
+                        Matcher::new(&app.find_query, app.find_options).err()
+                    };
+                    app.find_searched = None;
+                }
+                app.refresh_find_matches();
+
+                if ui.button("\u{25B2}").on_hover_text("Previous match (Shift+Enter, Shift+F3)").clicked() {
+                    step = Some(false);
+                }
+                if ui.button("\u{25BC}").on_hover_text("Next match (Enter, F3)").clicked() {
+                    step = Some(true);
+                }
+                if let Some(forward) = step {
+                    app.find_step(forward);
+                }
+                if !app.find_query.is_empty() && app.find_error.is_none() {
+                    ui.label(app.find_counter());
+                }
+                if ui.button("All Tabs").on_hover_text("List matches in every open tab").clicked() {
+                    app.find_in_open_tabs();
+                }
+
+                let mut in_selection = app.find_scope.is_some();
+                if ui
+                    .checkbox(&mut in_selection, "In selection")
+                    .on_hover_text("Only find and replace inside the text selected now")
+                    .changed()
+                {
+                    app.set_find_in_selection(in_selection);
+                }
+
+                ui.label("Replace:");
+                ui.text_edit_singleline(&mut app.replace_query);
+                if ui.button("Replace").on_hover_text("Replace the selected match and find the next").clicked() {
+                    app.replace_current();
+                }
+                if ui.button("Replace All").clicked() {
+                    app.replace_all_matches();
+                }
+                if ui.button("Close").clicked() {
+                    app.find_bar.close();
+                }
+                if let Some(err) = &app.find_error {
+                    ui.colored_label(ui.visuals().error_fg_color, err);
+                }
+            });
+        });
     }
+}
 
-    if input.modifiers.ctrl && input.key_pressed(Key::F) {
-        app.find_active = true;
+
+
+
+pub fn show_tabs_and_editor(ctx: &Context, app: &mut NotepadApp) {
+    egui::CentralPanel::default().show(ctx, |ui| {
+        app.show_tabs(ui);
+        ui.separator();
+        app.show_editor(ui);
+    });
+}
+
+pub fn handle_keyboard_shortcuts(ctx: &Context, app: &mut NotepadApp) {
+    // Consumed, so the focused TextEdit doesn't also run its own undoer or see the key.
+    for action in app.keybindings.pressed(ctx) {
+        match action {
+            // Falls back to Save As for untitled tabs.
+            Action::Save => {
+                app.save_tab(app.current_tab);
+            }
+            Action::Undo => {
+                if let Some(current) = app.buffers.get_mut(app.current_tab) {
+                    current.undo();
+                }
+            }
+            Action::Redo => {
+                if let Some(current) = app.buffers.get_mut(app.current_tab) {
+                    current.redo();
+                }
+            }
+            Action::Find => app.find_bar.open(),
+            Action::FindNext | Action::FindPrevious if !app.find_query.is_empty() => {
+                app.find_step(action == Action::FindNext);
+            }
+            Action::FindNext | Action::FindPrevious => {}
+            Action::GoToLine if !app.buffers.is_empty() => app.goto_dialog = Some(GotoDialog::default()),
+            Action::GoToLine => {}
+            Action::ZoomIn => app.set_font_size(app.font_size + 1.0),
+            Action::ZoomOut => app.set_font_size(app.font_size - 1.0),
+            Action::ZoomReset => app.set_font_size(fonts::DEFAULT_FONT_SIZE),
+        }
     }
 
-    if input.key_pressed(Key::Escape) {
-        app.find_active = false;
+    if app.find_bar.is_open() && ctx.input(|i| i.key_pressed(Key::Escape)) {
+        app.find_bar.close();
     }
 }
-
-// pub fn show_find_replace(app: &mut NotepadApp, ctx: &egui::Context) {
-//     if !app.find_active {
-//         return;
-//     }
-
-//     egui::TopBottomPanel::top("find_replace_bar").show(ctx, |ui| {
-//         ui.horizontal(|ui| {
-//             ui.label("Find:");
-//             ui.text_edit_singleline(&mut app.find_text);
-
-//             if ui.button("Find Next").clicked() {
-//                 if let Some(buffer) = app.buffers.get_mut(app.current_tab) {
-//                     if let Some(pos) = buffer.content[app.find_cursor..]
-//                         .find(&app.find_text)
-//                         .map(|idx| idx + app.find_cursor)
-//                     {
-//                         app.find_cursor = pos;
-//                         // Scroll to cursor or highlight logic can go here
-//                     } else {
-//                         app.find_cursor = 0; // wrap around
-//                     }
-//                 }
-//             }
-
-//             if ui.button("Close").clicked() {
-//                 app.find_active = false;
-//             }
-//         });
-//     });
-// }
\ No newline at end of file
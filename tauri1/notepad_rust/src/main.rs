@@ -20,6 +20,10 @@ enum ThemePreference {
 struct FileBuffer {
     name: String,
     content: String,
+    /// Bumped on every edit, so checking for unsaved changes is cheap.
+    revision: u64,
+    /// The revision last written to disk.
+    saved_revision: u64,
     file_path: Option<String>,
     undo_stack: Vec<String>,
     redo_stack: Vec<String>,
@@ -31,6 +35,8 @@ impl FileBuffer {
         Self {
             name: name.to_string(),
             content,
+            revision: 0,
+            saved_revision: 0,
             file_path: path,
             undo_stack: Vec::new(),
             redo_stack: Vec::new(),
@@ -38,6 +44,21 @@ impl FileBuffer {
         }
     }
 
+    fn is_dirty(&self) -> bool {
+        self.revision != self.saved_revision
+    }
+
+    fn edited(&mut self) {
+        self.revision += 1;
+        self.last_edit_time = Instant::now();
+    }
+
+    fn save_to(&mut self, path: &str) -> std::io::Result<()> {
+        fs::write(path, &self.content)?;
+        self.saved_revision = self.revision;
+        Ok(())
+    }
+
     fn push_undo(&mut self) {
         if self.undo_stack.last().map_or(true, |last| last != &self.content) {
             self.undo_stack.push(self.content.clone());
@@ -48,6 +69,7 @@ impl FileBuffer {
         if let Some(prev) = self.undo_stack.pop() {
             self.redo_stack.push(self.content.clone());
             self.content = prev;
+            self.edited();
         }
     }
 
@@ -55,6 +77,7 @@ impl FileBuffer {
         if let Some(next) = self.redo_stack.pop() {
             self.undo_stack.push(self.content.clone());
             self.content = next;
+            self.edited();
         }
     }
 }
@@ -71,6 +94,7 @@ struct NotepadApp {
     show_find: bool,
     editing_tab_index: Option<usize>,
     rename_buffer: String,
+    autosave: bool,
 }
 
 impl Default for NotepadApp {
@@ -87,6 +111,7 @@ impl Default for NotepadApp {
             show_find: false,
             editing_tab_index: None,
             rename_buffer: String::new(),
+            autosave: false,
         }
     }
 }
@@ -120,11 +145,16 @@ impl App for NotepadApp {
             }
         });
 
-        if let Some(buffer) = self.buffers.get_mut(self.current_tab) {
-            if buffer.last_edit_time.elapsed() > Duration::from_secs(10) {
-                if let Some(path) = &buffer.file_path {
-                    let _ = fs::write(path, &buffer.content);
-                    self.status = format!("Autosaved: {}", path);
+        if self.autosave {
+            for buffer in &mut self.buffers {
+                if !buffer.is_dirty() || buffer.last_edit_time.elapsed() < Duration::from_secs(10) {
+                    continue;
+                }
+                if let Some(path) = buffer.file_path.clone() {
+                    self.status = match buffer.save_to(&path) {
+                        Ok(()) => format!("Autosaved: {}", path),
+                        Err(err) => format!("Autosave failed: {}: {}", path, err),
+                    };
                 }
                 buffer.last_edit_time = Instant::now();
             }
@@ -149,10 +179,13 @@ impl App for NotepadApp {
                             let save_path = buffer.file_path.clone()
                                 .or_else(|| rfd::FileDialog::new().save_file().map(|p| p.display().to_string()));
                             if let Some(path) = save_path {
-                                if fs::write(&path, &buffer.content).is_ok() {
-                                    buffer.file_path = Some(path.clone());
-                                    self.status = format!("Saved: {}", path);
-                                }
+                                self.status = match buffer.save_to(&path) {
+                                    Ok(()) => {
+                                        buffer.file_path = Some(path.clone());
+                                        format!("Saved: {}", path)
+                                    }
+                                    Err(err) => format!("Save failed: {}: {}", path, err),
+                                };
                             }
                         }
                         ui.close_menu();
@@ -171,6 +204,7 @@ impl App for NotepadApp {
                     ui.label("Font Scale");
                     ui.add(egui::Slider::new(&mut self.font_scale, 0.5..=2.5).text("x"));
                     ui.checkbox(&mut self.wrap_text, "Wrap lines");
+                    ui.checkbox(&mut self.autosave, "Autosave after 10 s idle");
                     ui.separator();
                     ui.label("Theme");
                     ui.radio_value(&mut self.theme_pref, ThemePreference::System, "System");
@@ -189,7 +223,11 @@ impl App for NotepadApp {
                     ui.text_edit_singleline(&mut self.replace_query);
                     if ui.button("Replace All").clicked() {
                         if let Some(buffer) = self.buffers.get_mut(self.current_tab) {
-                            buffer.content = buffer.content.replace(&self.find_query, &self.replace_query);
+                            let replaced = buffer.content.replace(&self.find_query, &self.replace_query);
+                            if replaced != buffer.content {
+                                buffer.content = replaced;
+                                buffer.edited();
+                            }
                         }
                     }
                     if ui.button("Close").clicked() {
@@ -218,9 +256,11 @@ impl App for NotepadApp {
                                 if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
                                     if let Some(tab) = self.buffers.get_mut(i) {
                                         tab.name = self.rename_buffer.clone();
-                                        if let Some(path) = &tab.file_path {
-                                            let _ = fs::write(path, &tab.content);
-                                            self.status = format!("Renamed and saved: {}", path);
+                                        if let Some(path) = tab.file_path.clone() {
+                                            self.status = match tab.save_to(&path) {
+                                                Ok(()) => format!("Renamed and saved: {}", path),
+                                                Err(err) => format!("Renamed, but saving failed: {}: {}", path, err),
+                                            };
                                         }
                                     }
                                     self.editing_tab_index = None;
@@ -272,7 +312,9 @@ impl App for NotepadApp {
                         edit = edit.desired_width(f32::INFINITY);
                     }
 
-                    ui.add_sized(ui.available_size(), edit);
+                    if ui.add_sized(ui.available_size(), edit).changed() {
+                        buffer.edited();
+                    }
                     buffer.push_undo();
                 }
             });